@@ -1,11 +1,60 @@
-use anyhow::Result;
+use crate::error::ChatError;
+use chrono::{DateTime, Utc};
+use reqwest::header::HeaderMap;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Clone)]
 pub struct ClaudeClient {
     client: Client,
     api_key: String,
+    api_version: String,
+    parse_retries: u32,
+    recording: Option<Recording>,
+    extra_headers: HeaderMap,
+}
+
+/// Captures request/response pairs to disk, or serves previously captured responses instead of
+/// calling the API. Pairs are matched by a hash of the serialized request.
+#[derive(Debug, Clone)]
+pub enum Recording {
+    Record(PathBuf),
+    Replay(PathBuf),
+}
+
+pub const DEFAULT_MODEL: &str = "claude-sonnet-4-20250514";
+pub const DEFAULT_MAX_TOKENS: u32 = 4000;
+pub const DEFAULT_API_VERSION: &str = "2023-06-01";
+pub const DEFAULT_PARSE_RETRIES: u32 = 1;
+const PARSE_RETRY_DELAY: Duration = Duration::from_secs(1);
+// How much of an unparseable body to quote in the error
+const ERROR_SNIPPET_CHARS: usize = 200;
+
+// Requests asking for more output than this need the long-output beta, which only some models support
+pub const STANDARD_MAX_OUTPUT_TOKENS: u32 = 64_000;
+const LONG_OUTPUT_BETA: &str = "output-128k-2025-02-19";
+// Message fields kept in saved sessions that the API doesn't accept
+const DISPLAY_ONLY_FIELDS: [&str; 2] = ["stop_reason", "model"];
+// The smallest extended thinking budget the API accepts
+pub const MIN_THINKING_BUDGET: u32 = 1024;
+
+/// Expands the short names accepted by `/retry` to full model ids; anything else is used as given
+pub fn resolve_model_alias(model: &str) -> String {
+    match model {
+        "opus" => "claude-opus-4-20250514",
+        "sonnet" => "claude-sonnet-4-20250514",
+        "haiku" => "claude-3-5-haiku-20241022",
+        other => other,
+    }
+    .to_string()
+}
+
+pub fn supports_long_output(model: &str) -> bool {
+    model.starts_with("claude-3-7-sonnet")
 }
 
 #[derive(Debug, Serialize)]
@@ -13,13 +62,245 @@ pub struct MessageRequest {
     pub model: String,
     pub max_tokens: u32,
     pub messages: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub tools: Option<Vec<Tool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service_tier: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<Thinking>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub stream: bool,
+    /// Mark the end of the conversation as a prompt cache breakpoint; see `body`
+    #[serde(skip)]
+    pub cache_conversation: bool,
+}
+
+/// Extended thinking settings: Claude may reason for up to `budget_tokens` before answering
+#[derive(Debug, Clone, Serialize)]
+pub struct Thinking {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    budget_tokens: u32,
+}
+
+impl MessageRequest {
+    pub fn builder() -> MessageRequestBuilder {
+        MessageRequestBuilder {
+            request: MessageRequest {
+                model: DEFAULT_MODEL.to_string(),
+                max_tokens: DEFAULT_MAX_TOKENS,
+                messages: Vec::new(),
+                system: None,
+                tools: None,
+                temperature: None,
+                top_p: None,
+                service_tier: None,
+                thinking: None,
+                stream: false,
+                cache_conversation: false,
+            },
+        }
+    }
+
+    /// The JSON sent to the API. With `cache_conversation`, the last content block gets a
+    /// `cache_control` marker so everything up to it is cached for the next request; that is a
+    /// single breakpoint, well within the API's limit of four per request.
+    pub fn body(&self) -> Result<serde_json::Value, ChatError> {
+        let mut body = serde_json::to_value(self)?;
+        for message in body["messages"].as_array_mut().into_iter().flatten() {
+            if let Some(message) = message.as_object_mut() {
+                for field in DISPLAY_ONLY_FIELDS {
+                    message.remove(field);
+                }
+            }
+        }
+        if self.cache_conversation {
+            if let Some(content) = body["messages"]
+                .as_array_mut()
+                .and_then(|messages| messages.last_mut())
+                .map(|message| &mut message["content"])
+            {
+                if let Some(text) = content.as_str() {
+                    *content = serde_json::json!([{ "type": "text", "text": text }]);
+                }
+                if let Some(block) = content.as_array_mut().and_then(|blocks| blocks.last_mut()) {
+                    block["cache_control"] = serde_json::json!({ "type": "ephemeral" });
+                }
+            }
+        }
+        Ok(body)
+    }
+}
+
+pub struct MessageRequestBuilder {
+    request: MessageRequest,
+}
+
+impl MessageRequestBuilder {
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.request.model = model.into();
+        self
+    }
+
+    pub fn max_tokens(mut self, max_tokens: u32) -> Self {
+        self.request.max_tokens = max_tokens;
+        self
+    }
+
+    /// Sets the conversation, normalized into the alternating shape the API accepts
+    pub fn messages(mut self, messages: Vec<Message>) -> Self {
+        self.request.messages = normalize_messages(messages);
+        self
+    }
+
+    pub fn system(mut self, system: impl Into<String>) -> Self {
+        self.request.system = Some(system.into());
+        self
+    }
+
+    pub fn tools(mut self, tools: Vec<Tool>) -> Self {
+        self.request.tools = Some(tools);
+        self
+    }
+
+    pub fn temperature(mut self, temperature: f32) -> Self {
+        self.request.temperature = Some(temperature);
+        self
+    }
+
+    pub fn top_p(mut self, top_p: f32) -> Self {
+        self.request.top_p = Some(top_p);
+        self
+    }
+
+    pub fn service_tier(mut self, service_tier: impl Into<String>) -> Self {
+        self.request.service_tier = Some(service_tier.into());
+        self
+    }
+
+    pub fn cache_conversation(mut self, cache_conversation: bool) -> Self {
+        self.request.cache_conversation = cache_conversation;
+        self
+    }
+
+    /// Enables extended thinking with this token budget, which must be at least
+    /// MIN_THINKING_BUDGET and below max_tokens
+    pub fn thinking_budget(mut self, budget_tokens: u32) -> Self {
+        self.request.thinking = Some(Thinking { kind: "enabled", budget_tokens });
+        self
+    }
+
+    pub fn build(mut self) -> MessageRequest {
+        // The API rejects sampling changes alongside extended thinking
+        if self.request.thinking.is_some() {
+            self.request.temperature = None;
+            self.request.top_p = None;
+        }
+        self.request
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     pub role: String,
     pub content: MessageContent,
+    /// Why generation ended, for assistant messages; saved with sessions but never sent to the API
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_reason: Option<String>,
+    /// Model that produced an assistant message; saved with sessions but never sent to the API
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+}
+
+impl Message {
+    /// The message's text, ignoring tool blocks
+    pub fn text(&self) -> String {
+        match &self.content {
+            MessageContent::Text(text) => text.clone(),
+            MessageContent::Blocks(blocks) => blocks
+                .iter()
+                .filter_map(|block| match block {
+                    ContentBlock::Text { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    /// Characters of text, tool input and tool output; attachments aren't counted since their
+    /// base64 size says little about their token cost
+    pub fn char_count(&self) -> usize {
+        match &self.content {
+            MessageContent::Text(text) => text.chars().count(),
+            MessageContent::Blocks(blocks) => blocks
+                .iter()
+                .map(|block| match block {
+                    ContentBlock::Text { text } => text.chars().count(),
+                    ContentBlock::ToolUse { input, .. } => input.to_string().len(),
+                    ContentBlock::ToolResult { content, .. } => content.char_count(),
+                    ContentBlock::Thinking { thinking, .. } => thinking.chars().count(),
+                    ContentBlock::RedactedThinking { .. } | ContentBlock::Image { .. } | ContentBlock::Document { .. } => 0,
+                })
+                .sum(),
+        }
+    }
+
+    /// Whether this is something the user typed, as opposed to tool results sent on their behalf
+    pub fn is_user_turn(&self) -> bool {
+        self.role == "user"
+            && match &self.content {
+                MessageContent::Text(_) => true,
+                MessageContent::Blocks(blocks) => blocks
+                    .iter()
+                    .any(|block| !matches!(block, ContentBlock::ToolResult { .. })),
+            }
+    }
+}
+
+/// Makes a conversation valid for the API, which rejects anything that doesn't start with a user
+/// turn and alternate roles from there. Empty messages are dropped, adjacent messages with the
+/// same role are merged, and a placeholder user turn is added if the history starts with Claude.
+pub fn normalize_messages(messages: Vec<Message>) -> Vec<Message> {
+    let mut normalized: Vec<Message> = Vec::with_capacity(messages.len());
+    for message in messages.into_iter().filter(|message| !message.content.is_empty()) {
+        match normalized.last_mut() {
+            Some(previous) if previous.role == message.role => {
+                let mut blocks = std::mem::replace(&mut previous.content, MessageContent::Blocks(Vec::new())).into_blocks();
+                blocks.extend(message.content.into_blocks());
+                previous.content = MessageContent::Blocks(blocks);
+                previous.stop_reason = message.stop_reason;
+            }
+            _ => normalized.push(message),
+        }
+    }
+
+    if normalized.first().is_some_and(|message| message.role != "user") {
+        normalized.insert(0, Message {
+            role: "user".to_string(),
+            content: MessageContent::Text("(Earlier conversation omitted)".to_string()),
+            stop_reason: None,
+            model: None,
+        });
+    }
+
+    normalized
+}
+
+/// The last `max` messages for a sliding window, reaching one further back rather than starting
+/// on tool results whose call was cut off
+pub fn recent_messages(messages: &[Message], max: usize) -> &[Message] {
+    let mut start = messages.len().saturating_sub(max);
+    if start > 0 && messages.get(start).is_some_and(|message| message.role == "user" && !message.is_user_turn()) {
+        start -= 1;
+    }
+    &messages[start..]
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -29,6 +310,22 @@ pub enum MessageContent {
     Blocks(Vec<ContentBlock>),
 }
 
+impl MessageContent {
+    fn is_empty(&self) -> bool {
+        match self {
+            MessageContent::Text(text) => text.trim().is_empty(),
+            MessageContent::Blocks(blocks) => blocks.is_empty(),
+        }
+    }
+
+    fn into_blocks(self) -> Vec<ContentBlock> {
+        match self {
+            MessageContent::Text(text) => vec![ContentBlock::Text { text }],
+            MessageContent::Blocks(blocks) => blocks,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum ContentBlock {
@@ -43,8 +340,88 @@ pub enum ContentBlock {
     #[serde(rename = "tool_result")]
     ToolResult {
         tool_use_id: String,
-        content: String,
+        content: ToolResultContent,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        is_error: bool,
     },
+    #[serde(rename = "image")]
+    Image { source: MediaSource },
+    #[serde(rename = "document")]
+    Document { source: MediaSource },
+    /// Claude's reasoning, kept so it can be sent back unchanged as the API requires
+    #[serde(rename = "thinking")]
+    Thinking { thinking: String, signature: String },
+    #[serde(rename = "redacted_thinking")]
+    RedactedThinking { data: String },
+}
+
+/// A tool's output: plain text, or content blocks when it includes images
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ToolResultContent {
+    Text(String),
+    Blocks(Vec<ContentBlock>),
+}
+
+impl ToolResultContent {
+    /// Tools answer with text; a JSON array of text and image blocks (e.g. from a screenshot
+    /// tool) is passed on as blocks so Claude sees the images
+    pub fn from_output(output: String) -> Self {
+        match serde_json::from_str::<Vec<ContentBlock>>(&output) {
+            Ok(blocks)
+                if !blocks.is_empty()
+                    && blocks
+                        .iter()
+                        .all(|block| matches!(block, ContentBlock::Text { .. } | ContentBlock::Image { .. })) =>
+            {
+                ToolResultContent::Blocks(blocks)
+            }
+            _ => ToolResultContent::Text(output),
+        }
+    }
+
+    /// The result as text, with images reduced to a placeholder
+    pub fn to_text(&self) -> String {
+        match self {
+            ToolResultContent::Text(text) => text.clone(),
+            ToolResultContent::Blocks(blocks) => blocks
+                .iter()
+                .filter_map(|block| match block {
+                    ContentBlock::Text { text } => Some(text.clone()),
+                    ContentBlock::Image { source } => Some(format!("[image: {}]", source.media_type)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    fn char_count(&self) -> usize {
+        match self {
+            ToolResultContent::Text(text) => text.chars().count(),
+            ToolResultContent::Blocks(blocks) => blocks
+                .iter()
+                .map(|block| match block {
+                    ContentBlock::Text { text } => text.chars().count(),
+                    _ => 0,
+                })
+                .sum(),
+        }
+    }
+}
+
+impl From<String> for ToolResultContent {
+    fn from(text: String) -> Self {
+        ToolResultContent::Text(text)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaSource {
+    #[serde(rename = "type")]
+    pub source_type: String,
+    pub media_type: String,
+    pub data: String,
 }
 
 #[derive(Debug, Serialize)]
@@ -52,16 +429,18 @@ pub struct Tool {
     pub name: String,
     pub description: String,
     pub input_schema: serde_json::Value,
+    #[serde(skip)]
+    pub read_only: bool,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MessageResponse {
     pub content: Vec<ResponseContent>,
     pub stop_reason: Option<String>,
     pub usage: Usage,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum ResponseContent {
     #[serde(rename = "text")]
@@ -72,12 +451,339 @@ pub enum ResponseContent {
         name: String,
         input: serde_json::Value,
     },
+    #[serde(rename = "thinking")]
+    Thinking {
+        #[serde(default)]
+        thinking: String,
+        #[serde(default)]
+        signature: String,
+    },
+    #[serde(rename = "redacted_thinking")]
+    RedactedThinking { data: String },
 }
 
-#[derive(Debug, Deserialize)]
+impl ResponseContent {
+    /// Thinking becomes part of the conversation like any other block
+    pub fn thinking_block(self) -> Option<ContentBlock> {
+        match self {
+            ResponseContent::Thinking { thinking, signature } => Some(ContentBlock::Thinking { thinking, signature }),
+            ResponseContent::RedactedThinking { data } => Some(ContentBlock::RedactedThinking { data }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Usage {
     pub input_tokens: u32,
     pub output_tokens: u32,
+    /// Input tokens written to the prompt cache
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_creation_input_tokens: Option<u32>,
+    /// Input tokens read from the prompt cache instead of being processed again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_read_input_tokens: Option<u32>,
+    /// The tier that actually served the request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_tier: Option<String>,
+}
+
+impl Usage {
+    /// Adds another response's usage to this running total
+    pub fn add(&mut self, other: &Usage) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        if let Some(tokens) = other.cache_creation_input_tokens {
+            *self.cache_creation_input_tokens.get_or_insert(0) += tokens;
+        }
+        if let Some(tokens) = other.cache_read_input_tokens {
+            *self.cache_read_input_tokens.get_or_insert(0) += tokens;
+        }
+    }
+
+    /// Whether the prompt cache was read or written at all
+    pub fn used_cache(&self) -> bool {
+        self.cache_read_input_tokens.unwrap_or(0) > 0 || self.cache_creation_input_tokens.unwrap_or(0) > 0
+    }
+
+    /// Input tokens split into fresh, cache reads and cache writes, e.g. for the status bar
+    pub fn input_breakdown(&self) -> String {
+        format!(
+            "{} fresh, {} cached, {} written to cache",
+            self.input_tokens,
+            self.cache_read_input_tokens.unwrap_or(0),
+            self.cache_creation_input_tokens.unwrap_or(0)
+        )
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+enum StreamEvent {
+    #[serde(rename = "message_start")]
+    MessageStart { message: StreamMessageStart },
+    #[serde(rename = "content_block_start")]
+    ContentBlockStart { index: usize, content_block: ResponseContent },
+    #[serde(rename = "content_block_delta")]
+    ContentBlockDelta { index: usize, delta: ContentDelta },
+    #[serde(rename = "message_delta")]
+    MessageDelta { delta: MessageDeltaBody, usage: Option<DeltaUsage> },
+    #[serde(rename = "error")]
+    Error { error: StreamError },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamMessageStart {
+    usage: Usage,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+enum ContentDelta {
+    #[serde(rename = "text_delta")]
+    Text { text: String },
+    #[serde(rename = "input_json_delta")]
+    InputJson { partial_json: String },
+    #[serde(rename = "thinking_delta")]
+    Thinking { thinking: String },
+    #[serde(rename = "signature_delta")]
+    Signature { signature: String },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessageDeltaBody {
+    stop_reason: Option<String>,
+}
+
+/// The `anthropic-ratelimit-*` headers of a response: what is left of the request and token
+/// allowances and when each refills
+#[derive(Debug, Clone, Default)]
+pub struct RateLimits {
+    pub requests_remaining: Option<u64>,
+    pub requests_reset: Option<DateTime<Utc>>,
+    pub tokens_remaining: Option<u64>,
+    pub tokens_reset: Option<DateTime<Utc>>,
+}
+
+impl RateLimits {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+        let reset = |name: &str| {
+            header(name)
+                .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+                .map(|reset| reset.with_timezone(&Utc))
+        };
+        Self {
+            requests_remaining: header("anthropic-ratelimit-requests-remaining").and_then(|value| value.parse().ok()),
+            requests_reset: reset("anthropic-ratelimit-requests-reset"),
+            tokens_remaining: header("anthropic-ratelimit-tokens-remaining").and_then(|value| value.parse().ok()),
+            tokens_reset: reset("anthropic-ratelimit-tokens-reset"),
+        }
+    }
+
+    /// How long to wait before a request that may use up to `tokens` would fit, with the reason;
+    /// `None` when it can go now
+    pub fn wait_for(&self, tokens: u32) -> Option<(Duration, String)> {
+        let until = |reset: Option<DateTime<Utc>>| {
+            reset.and_then(|reset| (reset - Utc::now()).to_std().ok()).filter(|wait| !wait.is_zero())
+        };
+        let requests = match self.requests_remaining {
+            Some(0) => until(self.requests_reset).map(|wait| (wait, "no requests left".to_string())),
+            _ => None,
+        };
+        let tokens = match self.tokens_remaining {
+            Some(remaining) if remaining < u64::from(tokens) => until(self.tokens_reset)
+                .map(|wait| (wait, format!("{} tokens left", remaining))),
+            _ => None,
+        };
+        requests.into_iter().chain(tokens).max_by_key(|(wait, _)| *wait)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DeltaUsage {
+    output_tokens: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamError {
+    #[serde(rename = "type", default)]
+    kind: String,
+    message: String,
+}
+
+/// An in-flight streamed response, assembled into a `MessageResponse` as events arrive
+pub struct MessageStream {
+    response: Option<reqwest::Response>,
+    buffer: Vec<u8>,
+    content: Vec<ResponseContent>,
+    partial_json: Vec<String>,
+    stop_reason: Option<String>,
+    usage: Usage,
+    done: bool,
+    replayed: VecDeque<String>,
+    record_to: Option<PathBuf>,
+    rate_limits: RateLimits,
+}
+
+impl MessageStream {
+    fn new(response: Option<reqwest::Response>) -> Self {
+        let rate_limits = response
+            .as_ref()
+            .map(|response| RateLimits::from_headers(response.headers()))
+            .unwrap_or_default();
+        Self {
+            rate_limits,
+            response,
+            buffer: Vec::new(),
+            content: Vec::new(),
+            partial_json: Vec::new(),
+            stop_reason: None,
+            usage: Usage::default(),
+            done: false,
+            replayed: VecDeque::new(),
+            record_to: None,
+        }
+    }
+
+    fn replay(response: MessageResponse) -> Self {
+        let mut stream = Self::new(None);
+        stream.replayed = response
+            .content
+            .iter()
+            .filter_map(|content| match content {
+                ResponseContent::Text { text } => Some(text.clone()),
+                _ => None,
+            })
+            .collect();
+        stream.content = response.content;
+        stream.stop_reason = response.stop_reason;
+        stream.usage = response.usage;
+        stream.done = true;
+        stream
+    }
+
+    /// Returns the next piece of streamed text, or `None` once the message is complete
+    pub async fn next_delta(&mut self) -> Result<Option<String>, ChatError> {
+        if let Some(text) = self.replayed.pop_front() {
+            return Ok(Some(text));
+        }
+
+        loop {
+            // Events are split on raw bytes so multi-byte characters spanning chunks stay intact
+            if let Some(end) = self.buffer.windows(2).position(|window| window == b"\n\n") {
+                let event: Vec<u8> = self.buffer.drain(..end + 2).collect();
+                if let Some(text) = self.handle_event(&String::from_utf8_lossy(&event))? {
+                    return Ok(Some(text));
+                }
+                continue;
+            }
+
+            let Some(response) = self.response.as_mut().filter(|_| !self.done) else {
+                return Ok(None);
+            };
+
+            match response.chunk().await? {
+                Some(chunk) => self.buffer.extend(chunk.iter().filter(|&&byte| byte != b'\r')),
+                None => {
+                    self.done = true;
+                    if let Some(path) = self.record_to.take() {
+                        fs::write(path, serde_json::to_string_pretty(&self.snapshot())?)?;
+                    }
+                }
+            }
+        }
+    }
+
+    /// The rate limits reported with this response; empty for replayed ones
+    pub fn rate_limits(&self) -> &RateLimits {
+        &self.rate_limits
+    }
+
+    pub fn into_response(self) -> MessageResponse {
+        MessageResponse {
+            content: self.content,
+            stop_reason: self.stop_reason,
+            usage: self.usage,
+        }
+    }
+
+    fn snapshot(&self) -> MessageResponse {
+        MessageResponse {
+            content: self.content.clone(),
+            stop_reason: self.stop_reason.clone(),
+            usage: self.usage.clone(),
+        }
+    }
+
+    fn handle_event(&mut self, raw: &str) -> Result<Option<String>, ChatError> {
+        let data: String = raw
+            .lines()
+            .filter_map(|line| line.strip_prefix("data:"))
+            .map(str::trim_start)
+            .collect();
+        if data.is_empty() {
+            return Ok(None);
+        }
+
+        let event = serde_json::from_str::<StreamEvent>(&data)
+            .map_err(|e| ChatError::Parse(format!("Could not parse API event ({}): {}", e, snippet(&data))))?;
+        match event {
+            StreamEvent::MessageStart { message } => self.usage = message.usage,
+            StreamEvent::ContentBlockStart { index, content_block } => {
+                if index >= self.content.len() {
+                    self.partial_json.resize(index + 1, String::new());
+                    self.content.push(content_block);
+                }
+            }
+            StreamEvent::ContentBlockDelta { index, delta } => match delta {
+                ContentDelta::Text { text } => {
+                    if let Some(ResponseContent::Text { text: existing }) = self.content.get_mut(index) {
+                        existing.push_str(&text);
+                    }
+                    return Ok(Some(text));
+                }
+                ContentDelta::InputJson { partial_json } => {
+                    if let Some(buffer) = self.partial_json.get_mut(index) {
+                        buffer.push_str(&partial_json);
+                    }
+                    if let (Some(ResponseContent::ToolUse { input, .. }), Some(json)) =
+                        (self.content.get_mut(index), self.partial_json.get(index))
+                    {
+                        // The input is only valid JSON once the last fragment has arrived
+                        if let Ok(value) = serde_json::from_str(json) {
+                            *input = value;
+                        }
+                    }
+                }
+                ContentDelta::Thinking { thinking } => {
+                    if let Some(ResponseContent::Thinking { thinking: existing, .. }) = self.content.get_mut(index) {
+                        existing.push_str(&thinking);
+                    }
+                }
+                ContentDelta::Signature { signature } => {
+                    if let Some(ResponseContent::Thinking { signature: existing, .. }) = self.content.get_mut(index) {
+                        existing.push_str(&signature);
+                    }
+                }
+                ContentDelta::Other => {}
+            },
+            StreamEvent::MessageDelta { delta, usage } => {
+                self.stop_reason = delta.stop_reason;
+                if let Some(usage) = usage {
+                    self.usage.output_tokens = usage.output_tokens;
+                }
+            }
+            StreamEvent::Error { error } => return Err(ChatError::from_stream_error(error.kind, error.message)),
+            StreamEvent::Other => {}
+        }
+
+        Ok(None)
+    }
 }
 
 impl ClaudeClient {
@@ -85,31 +791,110 @@ impl ClaudeClient {
         Self {
             client: Client::new(),
             api_key,
+            api_version: DEFAULT_API_VERSION.to_string(),
+            parse_retries: DEFAULT_PARSE_RETRIES,
+            recording: None,
+            extra_headers: HeaderMap::new(),
         }
     }
 
-    pub async fn send_message(&self, request: MessageRequest) -> Result<MessageResponse> {
-        let response = self
-            .client
-            .post("https://api.anthropic.com/v1/messages")
-            .header("x-api-key", &self.api_key)
-            .header("anthropic-version", "2023-06-01")
-            .header("content-type", "application/json")
-            .json(&request)
-            .send()
-            .await?;
+    /// Headers added to every request after the built-in ones, replacing any with the same name
+    pub fn with_headers(mut self, headers: HeaderMap) -> Self {
+        self.extra_headers = headers;
+        self
+    }
+
+    /// Overrides the `anthropic-version` header sent with every request
+    pub fn with_api_version(mut self, api_version: impl Into<String>) -> Self {
+        self.api_version = api_version.into();
+        self
+    }
+
+    /// How many times to retry a response that isn't the expected event stream
+    pub fn with_parse_retries(mut self, parse_retries: u32) -> Self {
+        self.parse_retries = parse_retries;
+        self
+    }
+
+    pub fn with_recording(mut self, recording: Recording) -> Self {
+        self.recording = Some(recording);
+        self
+    }
+
+    pub async fn stream_message(&self, mut request: MessageRequest) -> Result<MessageStream, ChatError> {
+        request.stream = true;
 
-        if !response.status().is_success() {
-            let error_text = response.text().await?;
-            return Err(anyhow::anyhow!("API error: {}", error_text));
+        let body = request.body()?;
+        let mut record_to = None;
+        if let Some(recording) = &self.recording {
+            let name = format!("{:016x}", fnv1a(body.to_string().as_bytes()));
+            match recording {
+                Recording::Replay(dir) => {
+                    let path = dir.join(format!("{}.response.json", name));
+                    let contents = fs::read_to_string(&path).map_err(|_| {
+                        ChatError::Parse(format!("No recorded response for request {} in {}", name, dir.display()))
+                    })?;
+                    return Ok(MessageStream::replay(serde_json::from_str(&contents)?));
+                }
+                Recording::Record(dir) => {
+                    fs::create_dir_all(dir)?;
+                    let request_json = serde_json::to_string_pretty(&body)?;
+                    fs::write(dir.join(format!("{}.request.json", name)), request_json)?;
+                    record_to = Some(dir.join(format!("{}.response.json", name)));
+                }
+            }
         }
 
-        let message_response: MessageResponse = response.json().await?;
-        Ok(message_response)
+        let mut attempt = 0;
+        let response = loop {
+            let mut builder = self
+                .client
+                .post("https://api.anthropic.com/v1/messages")
+                .header("x-api-key", &self.api_key)
+                .header("anthropic-version", &self.api_version)
+                .header("content-type", "application/json");
+            if request.max_tokens > STANDARD_MAX_OUTPUT_TOKENS {
+                builder = builder.header("anthropic-beta", LONG_OUTPUT_BETA);
+            }
+            builder = builder.headers(self.extra_headers.clone());
+
+            let response = builder.json(&body).send().await?;
+
+            let status = response.status();
+            if !status.is_success() {
+                let retry_after = response
+                    .headers()
+                    .get("retry-after")
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.parse().ok())
+                    .map(Duration::from_secs);
+                let error_text = response.text().await?;
+                return Err(ChatError::from_status(status.as_u16(), retry_after, error_text));
+            }
+
+            // A successful status with some other body (e.g. an HTML page from a proxy during an
+            // outage) is usually transient, so it is retried before being reported
+            if is_event_stream(&response) {
+                break response;
+            }
+            let body = response.text().await?;
+            if attempt >= self.parse_retries {
+                return Err(ChatError::Parse(format!(
+                    "Unexpected response from the API (expected an event stream): {}",
+                    snippet(&body)
+                )));
+            }
+            attempt += 1;
+            tokio::time::sleep(PARSE_RETRY_DELAY).await;
+        };
+
+        let mut stream = MessageStream::new(Some(response));
+        stream.record_to = record_to;
+        Ok(stream)
     }
 
-    pub fn get_tools() -> Vec<Tool> {
-        vec![
+    pub fn get_tools(safe_mode: bool) -> Vec<Tool> {
+        let tools = vec![
             Tool {
                 name: "calculator".to_string(),
                 description: "Perform mathematical calculations".to_string(),
@@ -123,6 +908,7 @@ impl ClaudeClient {
                     },
                     "required": ["expression"]
                 }),
+                read_only: true,
             },
             Tool {
                 name: "weather".to_string(),
@@ -137,8 +923,239 @@ impl ClaudeClient {
                     },
                     "required": ["location"]
                 }),
+                read_only: true,
+            },
+            Tool {
+                name: "search_files".to_string(),
+                description: "Search text files under a directory for a string (case-insensitive), returning matching paths, line numbers and lines. Limited to the working directory; hidden, binary and very large files are skipped and results stop at 100 matches".to_string(),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "pattern": {
+                            "type": "string",
+                            "description": "Text to look for"
+                        },
+                        "path": {
+                            "type": "string",
+                            "description": "Directory to search, relative to the working directory (default: the working directory)"
+                        },
+                        "respect_gitignore": {
+                            "type": "boolean",
+                            "description": "Skip entries matching names or *.ext patterns in the searched directory's own .gitignore (default true); nested .gitignore files and other patterns are not read"
+                        }
+                    },
+                    "required": ["pattern"]
+                }),
+                read_only: true,
             },
-        ]
+            Tool {
+                name: "read_tool_result".to_string(),
+                description: "Read more of a tool result that was truncated for length. Truncated results end with a note giving the id and the offset to continue from".to_string(),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "id": {
+                            "type": "integer",
+                            "description": "Id of the truncated result"
+                        },
+                        "offset": {
+                            "type": "integer",
+                            "description": "Character to start reading from (default 0)"
+                        },
+                        "limit": {
+                            "type": "integer",
+                            "description": "Most characters to return (default and maximum: the truncation length)"
+                        }
+                    },
+                    "required": ["id"]
+                }),
+                read_only: true,
+            },
+            Tool {
+                name: "system_info".to_string(),
+                description: "Get the operating system, CPU architecture and working directory of the user's machine, plus any environment variables the user chose to share".to_string(),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {}
+                }),
+                read_only: true,
+            },
+            Tool {
+                name: "write_file".to_string(),
+                description: "Create or overwrite a file with the given contents. The user reviews a diff and must approve the write".to_string(),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "path": {
+                            "type": "string",
+                            "description": "Path of the file to write"
+                        },
+                        "content": {
+                            "type": "string",
+                            "description": "Complete new contents of the file"
+                        }
+                    },
+                    "required": ["path", "content"]
+                }),
+                read_only: false,
+            },
+        ];
+
+        // Safe mode only exposes tools without side effects, whatever else is enabled
+        tools
+            .into_iter()
+            .filter(|tool| !safe_mode || tool.read_only)
+            .collect()
+    }
+}
+
+fn is_event_stream(response: &reqwest::Response) -> bool {
+    response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/event-stream"))
+}
+
+/// The start of a body on one line, for error messages
+fn snippet(body: &str) -> String {
+    let collapsed = body.split_whitespace().collect::<Vec<_>>().join(" ");
+    if collapsed.chars().count() <= ERROR_SNIPPET_CHARS {
+        return collapsed;
     }
+    let truncated: String = collapsed.chars().take(ERROR_SNIPPET_CHARS).collect();
+    format!("{}…", truncated)
 }
 
+// Stable across runs and Rust versions, unlike `DefaultHasher`, so recordings stay replayable
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn reply() -> Message {
+        Message {
+            role: "assistant".to_string(),
+            content: MessageContent::Text("Hi".to_string()),
+            stop_reason: Some("max_tokens".to_string()),
+            model: Some(DEFAULT_MODEL.to_string()),
+        }
+    }
+
+    #[test]
+    fn display_fields_survive_a_save_but_are_not_sent() {
+        let saved: Message = serde_json::from_str(&serde_json::to_string(&reply()).unwrap()).unwrap();
+        assert_eq!(saved.stop_reason.as_deref(), Some("max_tokens"));
+        assert_eq!(saved.model.as_deref(), Some(DEFAULT_MODEL));
+
+        let user = Message {
+            role: "user".to_string(),
+            content: MessageContent::Text("Hello".to_string()),
+            stop_reason: None,
+            model: None,
+        };
+        let body = MessageRequest::builder().messages(vec![user, reply()]).build().body().unwrap();
+        let sent = &body["messages"][1];
+        assert!(sent.get("stop_reason").is_none());
+        assert!(sent.get("model").is_none());
+    }
+
+    fn message(role: &str, text: &str) -> Message {
+        Message {
+            role: role.to_string(),
+            content: MessageContent::Text(text.to_string()),
+            stop_reason: None,
+            model: None,
+        }
+    }
+
+    fn roles_and_content(messages: &[Message]) -> Vec<(String, Value)> {
+        messages
+            .iter()
+            .map(|message| (message.role.clone(), serde_json::to_value(&message.content).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn normalizing_keeps_an_alternating_conversation() {
+        let messages = vec![message("user", "Hello"), reply(), message("user", "Thanks")];
+        let normalized = normalize_messages(messages.clone());
+        assert_eq!(roles_and_content(&normalized), roles_and_content(&messages));
+    }
+
+    #[test]
+    fn normalizing_drops_empty_messages() {
+        let normalized = normalize_messages(vec![message("user", "Hello"), message("assistant", "  "), reply()]);
+        assert_eq!(roles_and_content(&normalized), roles_and_content(&[message("user", "Hello"), reply()]));
+    }
+
+    #[test]
+    fn normalizing_merges_adjacent_messages_with_the_same_role() {
+        let mut second = reply();
+        second.stop_reason = Some("end_turn".to_string());
+        let normalized = normalize_messages(vec![message("user", "Hello"), message("user", "Anyone there?"), reply(), second]);
+        assert_eq!(
+            roles_and_content(&normalized),
+            vec![
+                ("user".to_string(), json!([{ "type": "text", "text": "Hello" }, { "type": "text", "text": "Anyone there?" }])),
+                ("assistant".to_string(), json!([{ "type": "text", "text": "Hi" }, { "type": "text", "text": "Hi" }])),
+            ]
+        );
+        assert_eq!(normalized[1].stop_reason.as_deref(), Some("end_turn"));
+    }
+
+    #[test]
+    fn normalizing_adds_a_user_turn_before_a_leading_reply() {
+        let normalized = normalize_messages(vec![reply(), message("user", "Hello")]);
+        assert_eq!(normalized.len(), 3);
+        assert_eq!(normalized[0].role, "user");
+        assert_eq!(normalized[0].text(), "(Earlier conversation omitted)");
+        assert_eq!(normalized[1].role, "assistant");
+    }
+
+    #[test]
+    fn normalizing_an_empty_conversation_adds_nothing() {
+        assert!(normalize_messages(vec![message("user", "")]).is_empty());
+    }
+
+    fn tool_call() -> Message {
+        Message {
+            content: MessageContent::Blocks(vec![ContentBlock::ToolUse {
+                id: "call_1".to_string(),
+                name: "calculator".to_string(),
+                input: json!({ "expression": "1 + 1" }),
+            }]),
+            ..reply()
+        }
+    }
+
+    fn tool_result() -> Message {
+        Message {
+            content: MessageContent::Blocks(vec![ContentBlock::ToolResult {
+                tool_use_id: "call_1".to_string(),
+                content: ToolResultContent::Text("Result: 2".to_string()),
+                is_error: false,
+            }]),
+            ..message("user", "")
+        }
+    }
+
+    #[test]
+    fn history_window_never_starts_on_a_tool_result() {
+        let messages = vec![message("user", "What is 1 + 1?"), tool_call(), tool_result(), reply(), message("user", "Thanks")];
+        // A window of 3 would start on the result, so the call that asked for it comes along
+        let window = recent_messages(&messages, 3);
+        assert_eq!(window.len(), 4);
+        assert!(matches!(&window[0].content, MessageContent::Blocks(blocks) if matches!(blocks[0], ContentBlock::ToolUse { .. })));
+        // Windows starting on an ordinary turn are left as they are
+        assert_eq!(recent_messages(&messages, 2).len(), 2);
+        assert_eq!(recent_messages(&messages, 4).len(), 4);
+        assert_eq!(recent_messages(&messages, 10).len(), 5);
+    }
+}
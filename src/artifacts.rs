@@ -1,6 +1,10 @@
 use anyhow::Result;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
 use tempfile::TempDir;
 use uuid::Uuid;
 
@@ -12,17 +16,79 @@ pub struct Artifact {
     pub content: String,
 }
 
+/// What happens to an artifact when Tab opens it, or as soon as it arrives when its type has
+/// a configured action
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArtifactAction {
+    /// Open in the browser or the default app for the file
+    Open,
+    /// Save to the artifact directory and show where
+    Save,
+    /// Show in the chat's preview overlay
+    Inline,
+    /// Only keep it in the artifact list, for `/preview` or `/dump` later
+    Ignore,
+}
+
 pub struct ArtifactManager {
     temp_dir: TempDir,
+    include_types: Vec<String>,
+    exclude_types: Vec<String>,
+    actions: HashMap<String, ArtifactAction>,
 }
 
 impl ArtifactManager {
     pub fn new() -> Result<Self> {
         Ok(Self {
             temp_dir: TempDir::new()?,
+            include_types: Vec::new(),
+            exclude_types: Vec::new(),
+            actions: HashMap::new(),
+        })
+    }
+
+    /// Per content type actions; `text/*` style keys cover a family, and exact types win over them
+    pub fn with_actions(mut self, actions: HashMap<String, ArtifactAction>) -> Self {
+        self.actions = actions;
+        self
+    }
+
+    /// The action configured for a content type, if any
+    pub fn configured_action(&self, content_type: &str) -> Option<ArtifactAction> {
+        let family = content_type.split('/').next().map(|family| format!("{}/*", family));
+        self.actions
+            .get(content_type)
+            .or_else(|| family.and_then(|family| self.actions.get(&family)))
+            .copied()
+    }
+
+    /// The configured action, or else the built-in one: HTML and React open in the browser and
+    /// everything else is saved
+    pub fn action_for(&self, content_type: &str) -> ArtifactAction {
+        self.configured_action(content_type).unwrap_or(match content_type {
+            "text/html" | "application/vnd.ant.react" => ArtifactAction::Open,
+            _ => ArtifactAction::Save,
         })
     }
 
+    /// Limits extraction to the `include` content types (all when empty), minus `exclude`.
+    /// Entries like `text/*` match a whole family
+    pub fn with_type_filter(mut self, include: Vec<String>, exclude: Vec<String>) -> Self {
+        self.include_types = include;
+        self.exclude_types = exclude;
+        self
+    }
+
+    fn wants(&self, content_type: &str) -> bool {
+        let matches = |pattern: &String| match pattern.strip_suffix("/*") {
+            Some(family) => content_type.split('/').next() == Some(family),
+            None => pattern == content_type,
+        };
+        (self.include_types.is_empty() || self.include_types.iter().any(matches))
+            && !self.exclude_types.iter().any(matches)
+    }
+
     pub fn extract_artifacts(&self, text: &str) -> Vec<Artifact> {
         let mut artifacts = Vec::new();
         let mut in_artifact = false;
@@ -45,7 +111,7 @@ impl ArtifactManager {
                 content_lines.clear();
             } else if line.contains("</artifact>") {
                 in_artifact = false;
-                if let Some(mut artifact) = current_artifact.take() {
+                if let Some(mut artifact) = current_artifact.take().filter(|artifact| self.wants(&artifact.content_type)) {
                     artifact.content = content_lines.join("\n");
                     artifacts.push(artifact);
                 }
@@ -58,35 +124,99 @@ impl ArtifactManager {
         artifacts
     }
 
-    pub fn display_artifact(&self, artifact: &Artifact) -> Result<()> {
-        match artifact.content_type.as_str() {
-            "text/html" | "application/vnd.ant.react" => {
-                let file_path = self.temp_dir.path().join(format!("{}.html", artifact.id));
-                
-                let html_content = if artifact.content_type == "application/vnd.ant.react" {
-                    self.wrap_react_component(&artifact.content)
-                } else {
-                    artifact.content.clone()
+    /// Opens or saves an artifact, returning what happened for the status bar. Showing it
+    /// inline is up to the chat, so `Inline` and `Ignore` only leave it in the artifact list
+    pub fn display_artifact(&self, artifact: &Artifact, action: ArtifactAction) -> Result<String> {
+        Ok(match action {
+            ArtifactAction::Open => {
+                let file_path = match artifact.content_type.as_str() {
+                    "text/html" | "application/vnd.ant.react" => {
+                        let file_path = self.temp_dir.path().join(format!("{}.html", artifact.id));
+                        let html_content = if artifact.content_type == "application/vnd.ant.react" {
+                            let typescript = looks_like_typescript(&artifact.content);
+                            self.wrap_react_component(&artifact.content, typescript)
+                        } else {
+                            artifact.content.clone()
+                        };
+                        fs::write(&file_path, html_content)?;
+                        file_path
+                    }
+                    _ => self.save_artifact(artifact)?,
                 };
-                
-                fs::write(&file_path, html_content)?;
-                webbrowser::open(file_path.to_str().unwrap())?;
-            }
-            "text/javascript" | "text/typescript" => {
-                let extension = if artifact.content_type.contains("typescript") { "ts" } else { "js" };
-                let file_path = self.temp_dir.path().join(format!("{}.{}", artifact.id, extension));
-                fs::write(&file_path, &artifact.content)?;
-                println!("Saved {} artifact to: {}", extension.to_uppercase(), file_path.display());
+                // Headless and SSH sessions usually have no browser; the file is still worth pointing at
+                match webbrowser::open(&file_path.to_string_lossy()) {
+                    Ok(()) => format!("Opened '{}'", artifact.title),
+                    Err(e) => format!(
+                        "Couldn't open a browser ({}) - '{}' is saved at {}",
+                        e,
+                        artifact.title,
+                        file_path.display()
+                    ),
+                }
             }
-            _ => {
-                println!("Artifact '{}' ({})", artifact.title, artifact.content_type);
-                println!("{}", artifact.content);
+            ArtifactAction::Save => format!(
+                "Saved '{}' ({}) to: {}",
+                artifact.title,
+                artifact.content_type,
+                self.save_artifact(artifact)?.display()
+            ),
+            ArtifactAction::Inline | ArtifactAction::Ignore => {
+                format!("'{}' is kept in the chat - /preview to view it", artifact.title)
             }
-        }
-        Ok(())
+        })
     }
 
-    fn wrap_react_component(&self, content: &str) -> String {
+    /// Writes an artifact's source into the artifact directory, returning where it went
+    pub fn save_artifact(&self, artifact: &Artifact) -> Result<PathBuf> {
+        let path = self.temp_dir.path().join(format!("{}.{}", artifact.id, source_extension(&artifact.content_type)));
+        fs::write(&path, &artifact.content)?;
+        Ok(path)
+    }
+
+    pub fn dir(&self) -> &Path {
+        self.temp_dir.path()
+    }
+
+    fn wrap_react_component(&self, content: &str, typescript: bool) -> String {
+        let (content, component) = prepare_react_source(content);
+        let mount = match component {
+            Some(component) => format!(
+                "try {{\n            ReactDOM.render(React.createElement({}), document.getElementById('root'));\n        }} catch (error) {{\n            document.getElementById('root').textContent = 'Failed to render component: ' + error.message;\n        }}",
+                component
+            ),
+            None => "document.getElementById('root').textContent = 'No React component found: define an App component or use export default.';".to_string(),
+        };
+
+        // Babel's script-tag mode can't enable the TypeScript preset for TSX, so typed components
+        // are kept as inert text and transformed explicitly
+        let script = if typescript {
+            format!(
+                r#"<script type="text/plain" id="artifact-source">
+        {content}
+        {mount}
+    </script>
+    <script>
+        const source = document.getElementById('artifact-source').textContent;
+        const {{ code }} = Babel.transform(source, {{
+            filename: 'artifact.tsx',
+            presets: [['typescript', {{ isTSX: true, allExtensions: true }}], 'react'],
+        }});
+        new Function(code)();
+    </script>"#,
+                content = content.replace("</script", "<\\/script"),
+                mount = mount
+            )
+        } else {
+            format!(
+                r#"<script type="text/babel">
+        {content}
+        {mount}
+    </script>"#,
+                content = content,
+                mount = mount
+            )
+        };
+
         format!(
             r#"<!DOCTYPE html>
 <html lang="en">
@@ -101,13 +231,10 @@ impl ArtifactManager {
 </head>
 <body>
     <div id="root"></div>
-    <script type="text/babel">
-        {content}
-        ReactDOM.render(React.createElement(App || (() => React.createElement('div', null, 'Component not found'))), document.getElementById('root'));
-    </script>
+    {script}
 </body>
 </html>"#,
-            content = content
+            script = script
         )
     }
 
@@ -123,4 +250,167 @@ impl ArtifactManager {
     }
 }
 
+/// Rewrites module-style React source so it runs as a plain Babel script: imports are dropped
+/// (hooks imported from 'react' are pulled off the global `React` instead) and exports are
+/// unwrapped. Returns the source along with the component to render: the default export, else
+/// `App`, else the first component defined.
+fn prepare_react_source(content: &str) -> (String, Option<String>) {
+    let mut lines = Vec::new();
+    let mut default_export = None;
+    let mut pending_import: Option<String> = None;
+
+    for line in content.lines() {
+        let trimmed = line.trim();
+
+        if let Some(mut import) = pending_import.take().or_else(|| trimmed.starts_with("import ").then(String::new)) {
+            import.push_str(trimmed);
+            import.push(' ');
+            if !(import.contains(" from ") || import.trim_end().ends_with(';')) {
+                pending_import = Some(import);
+            } else if let Some(hooks) = react_named_imports(&import) {
+                lines.push(format!("const {{ {} }} = React;", hooks));
+            }
+            continue;
+        }
+
+        if let Some(rest) = trimmed.strip_prefix("export default ") {
+            let target = rest.trim_end_matches(';').trim();
+            if is_identifier(target) {
+                default_export = Some(target.to_string());
+            } else if let Some(name) = declared_name(rest) {
+                default_export = Some(name);
+                lines.push(rest.to_string());
+            } else {
+                default_export = Some("__DefaultExport".to_string());
+                lines.push(format!("const __DefaultExport = {}", rest));
+            }
+            continue;
+        }
+
+        match trimmed.strip_prefix("export ") {
+            Some(rest) => lines.push(rest.to_string()),
+            None => lines.push(line.to_string()),
+        }
+    }
+
+    let components: Vec<String> = lines.iter().filter_map(|line| declared_name(line.trim())).collect();
+    let component = default_export
+        .or_else(|| components.iter().find(|name| *name == "App").cloned())
+        .or_else(|| components.into_iter().next());
+
+    (lines.join("\n"), component)
+}
+
+/// Writes every artifact's source into `dir`, creating it if needed. A later version of an
+/// artifact replaces an earlier one with the same identifier
+pub fn dump_artifacts(artifacts: &[Artifact], dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    for artifact in artifacts {
+        let path = dir.join(format!("{}.{}", artifact.id, source_extension(&artifact.content_type)));
+        fs::write(path, &artifact.content)?;
+    }
+    Ok(())
+}
+
+pub fn source_extension(content_type: &str) -> &'static str {
+    match content_type {
+        "text/html" => "html",
+        "application/vnd.ant.react" => "jsx",
+        "text/javascript" => "js",
+        "text/typescript" => "ts",
+        "text/markdown" => "md",
+        "image/svg+xml" => "svg",
+        "application/vnd.ant.mermaid" => "mmd",
+        _ => "txt",
+    }
+}
+
+/// Opens a directory in the system file manager. Fails without a graphical session, so the
+/// caller can show the path instead
+pub fn open_directory(path: &Path) -> Result<()> {
+    let opener = if cfg!(target_os = "macos") {
+        "open"
+    } else if cfg!(target_os = "windows") {
+        "explorer"
+    } else {
+        if env::var_os("DISPLAY").is_none() && env::var_os("WAYLAND_DISPLAY").is_none() {
+            return Err(anyhow::anyhow!("no graphical session"));
+        }
+        "xdg-open"
+    };
+    Command::new(opener)
+        .arg(path)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("failed to run {}: {}", opener, e))?;
+    Ok(())
+}
+
+/// Type annotations and declarations that only appear in TypeScript sources
+fn looks_like_typescript(content: &str) -> bool {
+    const MARKERS: [&str; 9] = [
+        "interface ", "type Props", ": React.FC", ": string", ": number", ": boolean",
+        "useState<", "useRef<", " as const",
+    ];
+    MARKERS.iter().any(|marker| content.contains(marker))
+}
+
+/// `useState, useEffect as useFx` from `import React, { useState, useEffect as useFx } from 'react'`,
+/// as destructuring syntax
+fn react_named_imports(import: &str) -> Option<String> {
+    if !(import.contains("'react'") || import.contains("\"react\"")) {
+        return None;
+    }
+    let start = import.find('{')?;
+    let end = import[start..].find('}')? + start;
+    let names: Vec<String> = import[start + 1..end]
+        .split(',')
+        .map(|name| name.trim().replace(" as ", ": "))
+        .filter(|name| !name.is_empty())
+        .collect();
+    (!names.is_empty()).then(|| names.join(", "))
+}
+
+/// The name of a capitalized component declared by a line like `function Foo(`,
+/// `const Foo = (...) =>` or `class Foo extends ...`
+fn declared_name(line: &str) -> Option<String> {
+    let rest = line
+        .strip_prefix("function ")
+        .or_else(|| line.strip_prefix("async function "))
+        .or_else(|| line.strip_prefix("class "))
+        .or_else(|| line.strip_prefix("const "))
+        .or_else(|| line.strip_prefix("let "))?;
+    let name: String = rest.chars().take_while(|c| c.is_alphanumeric() || *c == '_' || *c == '$').collect();
+    let is_component = name.chars().next().is_some_and(|c| c.is_ascii_uppercase());
+    if !is_component {
+        return None;
+    }
+    if line.starts_with("const ") || line.starts_with("let ") {
+        let value = rest[name.len()..].trim_start().strip_prefix('=')?;
+        if !(value.contains("=>") || value.contains("function") || value.contains("React.")) {
+            return None;
+        }
+    }
+    Some(name)
+}
+
+fn is_identifier(text: &str) -> bool {
+    !text.is_empty()
+        && !text.starts_with(|c: char| c.is_ascii_digit())
+        && text.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TSX_COMPONENT: &str = "interface Props { name: string }\n\nexport default function App({ name }: Props) {\n  return <h1>Hello {name}</h1>;\n}";
+
+    #[test]
+    fn tsx_component_html_matches_snapshot() {
+        let manager = ArtifactManager::new().unwrap();
+        let html = manager.wrap_react_component(TSX_COMPONENT, looks_like_typescript(TSX_COMPONENT));
+        assert_eq!(html, include_str!("snapshots/tsx_component.html"));
+    }
+}
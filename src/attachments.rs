@@ -0,0 +1,54 @@
+use anyhow::Result;
+use base64::Engine;
+use std::fs;
+use std::path::Path;
+
+use crate::api::{ContentBlock, MediaSource};
+
+/// Reads a file into a content block: images and PDFs are base64-encoded, anything else is
+/// attached as a plain text document
+pub fn load_attachment(path: &Path) -> Result<ContentBlock> {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+
+    let image_type = match extension.as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        _ => None,
+    };
+
+    if let Some(media_type) = image_type {
+        return Ok(ContentBlock::Image {
+            source: base64_source(media_type, &fs::read(path)?),
+        });
+    }
+
+    if extension == "pdf" {
+        return Ok(ContentBlock::Document {
+            source: base64_source("application/pdf", &fs::read(path)?),
+        });
+    }
+
+    let text = fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("{} is not a supported image, PDF or text file: {}", path.display(), e))?;
+    Ok(ContentBlock::Document {
+        source: MediaSource {
+            source_type: "text".to_string(),
+            media_type: "text/plain".to_string(),
+            data: text,
+        },
+    })
+}
+
+fn base64_source(media_type: &str, bytes: &[u8]) -> MediaSource {
+    MediaSource {
+        source_type: "base64".to_string(),
+        media_type: media_type.to_string(),
+        data: base64::engine::general_purpose::STANDARD.encode(bytes),
+    }
+}
@@ -0,0 +1,115 @@
+use anyhow::Result;
+use serde_json::json;
+use std::fs;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::api::{ClaudeClient, Usage};
+use crate::config::Config;
+use crate::headless::{complete, tool_handler};
+use crate::mcp::ToolLog;
+
+const PROGRESS_BAR_WIDTH: usize = 24;
+
+/// Sends each non-empty line of `path` (or stdin for `-`) as its own conversation. Results go
+/// to stdout as one JSON object per line, in input order; progress goes to stderr so stdout
+/// stays machine-readable.
+pub async fn run(
+    path: &Path,
+    client: ClaudeClient,
+    config: Config,
+    tool_log: Option<ToolLog>,
+    system: Option<String>,
+) -> Result<()> {
+    let input = if path == Path::new("-") {
+        let mut input = String::new();
+        io::stdin().read_to_string(&mut input)?;
+        input
+    } else {
+        fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read batch file {}: {}", path.display(), e))?
+    };
+    let prompts: Vec<&str> = input.lines().map(str::trim).filter(|line| !line.is_empty()).collect();
+
+    let mcp_handler = tool_handler(&config, tool_log);
+
+    let started = Instant::now();
+    let mut usage = Usage::default();
+    let mut failures = 0;
+    let mut stdout = io::stdout();
+    let mut pause: Option<(Duration, String)> = None;
+    report_progress(0, prompts.len(), started.elapsed(), &usage);
+    for (index, prompt) in prompts.iter().enumerate() {
+        // Waiting out an exhausted allowance beats sending a request that's bound to get a 429
+        if let Some((wait, reason)) = pause.take() {
+            report_pause(wait, &reason);
+            tokio::time::sleep(wait).await;
+        }
+        let result = match complete(&client, &mcp_handler, &config, prompt.to_string(), system.as_deref()).await {
+            Ok(completion) => {
+                usage.add(&completion.usage);
+                pause = completion.rate_limits.wait_for(config.max_tokens());
+                json!({
+                    "prompt": prompt,
+                    "response": completion.text,
+                    "stop_reason": completion.stop_reason,
+                    "input_tokens": completion.usage.input_tokens,
+                    "output_tokens": completion.usage.output_tokens,
+                    "cache_read_input_tokens": completion.usage.cache_read_input_tokens,
+                    "cache_creation_input_tokens": completion.usage.cache_creation_input_tokens,
+                })
+            }
+            Err(e) => {
+                failures += 1;
+                json!({ "prompt": prompt, "error": e.to_string() })
+            }
+        };
+        writeln!(stdout, "{}", result)?;
+        stdout.flush()?;
+        report_progress(index + 1, prompts.len(), started.elapsed(), &usage);
+    }
+
+    if io::stderr().is_terminal() {
+        eprintln!();
+    }
+    if failures > 0 {
+        eprintln!("{} of {} prompts failed", failures, prompts.len());
+    }
+    Ok(())
+}
+
+/// Notes a rate limit pause on its own line, leaving the progress line below it
+fn report_pause(wait: Duration, reason: &str) {
+    let line = format!("Rate limit: {}, waiting {}s for it to reset", reason, wait.as_secs_f32().ceil());
+    if io::stderr().is_terminal() {
+        eprintln!("\r\x1b[2K{}", line);
+    } else {
+        eprintln!("{}", line);
+    }
+}
+
+/// Redraws the progress line in place on a terminal; when stderr is redirected, each update is
+/// written as its own line instead
+fn report_progress(done: usize, total: usize, elapsed: Duration, usage: &Usage) {
+    let filled = (done * PROGRESS_BAR_WIDTH).checked_div(total).unwrap_or(PROGRESS_BAR_WIDTH);
+    let seconds = elapsed.as_secs();
+    let line = format!(
+        "[{}{}] {}/{} prompts, {:02}:{:02}:{:02} elapsed, {} input / {} output tokens",
+        "#".repeat(filled),
+        "-".repeat(PROGRESS_BAR_WIDTH - filled),
+        done,
+        total,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        usage.input_tokens,
+        usage.output_tokens
+    );
+    if io::stderr().is_terminal() {
+        eprint!("\r{}", line);
+        let _ = io::stderr().flush();
+    } else {
+        eprintln!("{}", line);
+    }
+}
@@ -0,0 +1,173 @@
+use ratatui::style::Color;
+use serde::Deserialize;
+use std::env;
+
+/// How many colors the terminal can show, which decides how syntax highlighting colors are emitted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+pub enum ColorLevel {
+    #[value(name = "truecolor")]
+    #[serde(rename = "truecolor")]
+    TrueColor,
+    #[value(name = "256")]
+    #[serde(rename = "256")]
+    Ansi256,
+    #[value(name = "16")]
+    #[serde(rename = "16")]
+    Ansi16,
+    #[value(name = "none")]
+    #[serde(rename = "none")]
+    None,
+}
+
+/// The terminal's background, which decides the syntax theme and the UI's text colors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Background {
+    Light,
+    Dark,
+}
+
+/// UI colors chosen to stay readable on a background
+pub struct Palette {
+    pub text: Color,
+    pub input: Color,
+    pub status: Color,
+    pub selection: Color,
+    /// Role labels
+    pub user: Color,
+    pub assistant: Color,
+    pub tool_output: Color,
+    /// Tool calls, attachments and inline code spans
+    pub tool_call: Color,
+    pub attachment: Color,
+    pub inline_code: Color,
+    /// Line numbers, code labels and notices that should stay in the background
+    pub muted: Color,
+}
+
+impl Background {
+    /// Reads `COLORFGBG` ("15;0" means light text on black), which many terminals set; dark
+    /// is assumed when it's missing
+    pub fn detect() -> Self {
+        let background = env::var("COLORFGBG")
+            .ok()
+            .and_then(|value| value.rsplit(';').next().and_then(|bg| bg.parse::<u8>().ok()));
+        match background {
+            Some(7 | 9..=15) => Background::Light,
+            _ => Background::Dark,
+        }
+    }
+
+    pub fn default_theme(self) -> &'static str {
+        match self {
+            Background::Light => "InspiredGitHub",
+            Background::Dark => "base16-ocean.dark",
+        }
+    }
+
+    pub fn palette(self) -> Palette {
+        match self {
+            Background::Light => Palette {
+                text: Color::Black,
+                input: Color::Blue,
+                status: Color::DarkGray,
+                selection: Color::Gray,
+                user: Color::Blue,
+                assistant: Color::Green,
+                tool_output: Color::Magenta,
+                tool_call: Color::Red,
+                attachment: Color::Blue,
+                inline_code: Color::Magenta,
+                muted: Color::DarkGray,
+            },
+            Background::Dark => Palette {
+                text: Color::White,
+                input: Color::Yellow,
+                status: Color::Gray,
+                selection: Color::DarkGray,
+                user: Color::Cyan,
+                assistant: Color::Green,
+                tool_output: Color::Magenta,
+                tool_call: Color::Yellow,
+                attachment: Color::LightBlue,
+                inline_code: Color::Yellow,
+                muted: Color::DarkGray,
+            },
+        }
+    }
+}
+
+// The 16 standard colors with typical xterm values, for picking the nearest match
+const ANSI16: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::Red, (205, 0, 0)),
+    (Color::Green, (0, 205, 0)),
+    (Color::Yellow, (205, 205, 0)),
+    (Color::Blue, (0, 0, 238)),
+    (Color::Magenta, (205, 0, 205)),
+    (Color::Cyan, (0, 205, 205)),
+    (Color::Gray, (229, 229, 229)),
+    (Color::DarkGray, (127, 127, 127)),
+    (Color::LightRed, (255, 0, 0)),
+    (Color::LightGreen, (0, 255, 0)),
+    (Color::LightYellow, (255, 255, 0)),
+    (Color::LightBlue, (92, 92, 255)),
+    (Color::LightMagenta, (255, 0, 255)),
+    (Color::LightCyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+impl ColorLevel {
+    /// Guesses the terminal's capability from `NO_COLOR`, `COLORTERM` and `TERM`
+    pub fn detect() -> Self {
+        if env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+            return ColorLevel::None;
+        }
+        if env::var("COLORTERM").is_ok_and(|value| value == "truecolor" || value == "24bit") {
+            return ColorLevel::TrueColor;
+        }
+        match env::var("TERM").unwrap_or_default().as_str() {
+            "dumb" => ColorLevel::None,
+            term if term.contains("256color") => ColorLevel::Ansi256,
+            _ => ColorLevel::Ansi16,
+        }
+    }
+
+    /// Converts a color to the closest one this level can show, or `None` for no color at all
+    pub fn adapt(self, color: Color) -> Option<Color> {
+        match (self, color) {
+            (ColorLevel::None, _) => None,
+            (ColorLevel::TrueColor, color) => Some(color),
+            (ColorLevel::Ansi256, Color::Rgb(r, g, b)) => Some(Color::Indexed(rgb_to_256(r, g, b))),
+            (ColorLevel::Ansi16, Color::Rgb(r, g, b)) => Some(nearest_ansi16(r, g, b)),
+            (_, color) => Some(color),
+        }
+    }
+}
+
+/// Maps to the 6x6x6 color cube or the grayscale ramp of the 256-color palette
+fn rgb_to_256(r: u8, g: u8, b: u8) -> u8 {
+    if r == g && g == b {
+        return match r {
+            0..=7 => 16,
+            248..=255 => 231,
+            _ => 232 + ((r as u16 - 8) * 24 / 240) as u8,
+        };
+    }
+    let level = |channel: u8| ((channel as u16 * 5 + 127) / 255) as u8;
+    16 + 36 * level(r) + 6 * level(g) + level(b)
+}
+
+fn nearest_ansi16(r: u8, g: u8, b: u8) -> Color {
+    let distance = |(cr, cg, cb): (u8, u8, u8)| {
+        let dr = r as i32 - cr as i32;
+        let dg = g as i32 - cg as i32;
+        let db = b as i32 - cb as i32;
+        dr * dr + dg * dg + db * db
+    };
+    ANSI16
+        .iter()
+        .min_by_key(|(_, rgb)| distance(*rgb))
+        .map(|(color, _)| *color)
+        .unwrap_or(Color::Reset)
+}
@@ -0,0 +1,66 @@
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Undo,
+    Retry(Option<String>),
+    RetryWith(String),
+    Resend,
+    Attach(String),
+    Detach,
+    Save(String),
+    Fork(String),
+    Sessions,
+    Export { format: String, name: String },
+    CopyAll(String),
+    Dump(String),
+    Preview(String),
+    Raw,
+    Debug(String),
+    Note(String),
+    System(String),
+    Summarize,
+    Think(String),
+    Why,
+    Unknown(String),
+}
+
+impl Command {
+    /// Parses a slash command from the input box, returning `None` for regular messages
+    pub fn parse(input: &str) -> Option<Self> {
+        let input = input.trim();
+        let rest = input.strip_prefix('/')?;
+        let (name, args) = match rest.split_once(char::is_whitespace) {
+            Some((name, args)) => (name, args.trim()),
+            None => (rest, ""),
+        };
+
+        Some(match name {
+            "undo" => Command::Undo,
+            "resend" => Command::Resend,
+            "retry" => Command::Retry((!args.is_empty()).then(|| args.to_string())),
+            "retry-with" => Command::RetryWith(args.to_string()),
+            "attach" => Command::Attach(args.to_string()),
+            "detach" => Command::Detach,
+            "save" => Command::Save(args.to_string()),
+            "fork" => Command::Fork(args.to_string()),
+            "sessions" => Command::Sessions,
+            "copy-all" => Command::CopyAll(args.to_string()),
+            "dump" => Command::Dump(args.to_string()),
+            "preview" => Command::Preview(args.to_string()),
+            "raw" => Command::Raw,
+            "note" => Command::Note(args.to_string()),
+            "debug" => Command::Debug(args.to_string()),
+            "system" => Command::System(args.to_string()),
+            "summarize" => Command::Summarize,
+            "think" => Command::Think(args.to_string()),
+            "why" => Command::Why,
+            "export" => {
+                let (format, name) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+                Command::Export {
+                    format: format.to_string(),
+                    name: name.trim().to_string(),
+                }
+            }
+            _ => Command::Unknown(name.to_string()),
+        })
+    }
+}
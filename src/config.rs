@@ -0,0 +1,320 @@
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::api::{DEFAULT_API_VERSION, DEFAULT_MAX_TOKENS, DEFAULT_MODEL, DEFAULT_PARSE_RETRIES, MIN_THINKING_BUDGET};
+use crate::artifacts::ArtifactAction;
+use crate::color::{Background, ColorLevel};
+use crate::mcp::{ExternalTool, DEFAULT_MAX_TOOL_RESULT_CHARS, DEFAULT_NUMBER_PRECISION};
+use crate::redact::Redactor;
+use crate::wrap::Overflow;
+
+const DEFAULT_MAX_TOOL_ITERATIONS: u32 = 10;
+const DEFAULT_HISTORY_SIZE: usize = 1000;
+const DEFAULT_SERVE_MAX_CONCURRENT: usize = 4;
+const DEFAULT_SERVE_MAX_QUEUED: usize = 32;
+const DEFAULT_SERVE_QUEUE_TIMEOUT_SECS: u64 = 60;
+
+/// Settings read from `~/.config/claude-chatbot/config.toml`; command line flags take precedence
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Used when neither --api-key nor ANTHROPIC_API_KEY is set
+    pub api_key: Option<String>,
+    /// Base directory for exports and saved sessions
+    pub output_dir: Option<PathBuf>,
+    pub model: Option<String>,
+    pub max_tokens: Option<u32>,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    /// Extended thinking budget in tokens; thinking is off when unset
+    pub thinking_budget: Option<u32>,
+    /// How many rounds of tool calls Claude may make before control returns to the user
+    pub max_tool_iterations: Option<u32>,
+    /// Only the most recent messages are sent to the API, though all stay on screen; unlimited when unset
+    pub max_history: Option<usize>,
+    /// Tools whose call ends the tool loop, handing control back to the user, e.g. a `finish` tool
+    pub stop_tools: Vec<String>,
+    /// Value of the `anthropic-version` header
+    pub api_version: Option<String>,
+    /// Requested `service_tier`, e.g. `auto` or `standard_only`; left to the API when unset
+    pub service_tier: Option<String>,
+    /// Retries for responses that aren't the expected event stream
+    pub parse_retries: Option<u32>,
+    pub safe: bool,
+    /// Send no tools at all, so Claude can only answer in text
+    pub no_tools: bool,
+    /// Cache the conversation so far on every request so only new turns are billed at full rate
+    pub cache_conversation: bool,
+    /// Replace API keys, tokens and similar secrets in tool results with [REDACTED]
+    pub redact_secrets: bool,
+    /// Extra regular expressions to redact from tool results, whether or not `redact_secrets` is on
+    pub redact_patterns: Vec<String>,
+    /// Characters of a tool result sent at once; Claude reads the rest with read_tool_result.
+    /// 0 sends results whole
+    pub max_tool_result_chars: Option<usize>,
+    /// Maximum decimal places in numeric tool results
+    pub number_precision: Option<usize>,
+    /// Color support to render for; detected from the environment when unset
+    pub color: Option<ColorLevel>,
+    /// Terminal background; detected from COLORFGBG when unset
+    pub background: Option<Background>,
+    /// Syntax highlighting themes for each background, overriding the built-in choices
+    pub light_theme: Option<String>,
+    pub dark_theme: Option<String>,
+    /// Show the language above fenced code blocks
+    pub code_labels: bool,
+    /// Number the lines of code blocks
+    pub line_numbers: bool,
+    /// Show simple LaTeX math like `$x^2$` with Unicode symbols (x²) instead of its source
+    pub unicode_math: bool,
+    /// Columns of text the chat may be, centered on wider terminals; full width when unset
+    pub max_width: Option<u16>,
+    /// Fit more on screen: no blank lines between messages and role tags inline with the text
+    pub compact: bool,
+    /// "wrap" (the default) or "scroll" for code lines wider than the pane
+    pub code_overflow: Overflow,
+    /// Artifact content types to extract (everything when empty) and ones to leave in the chat
+    /// text only; `text/*` style entries match a whole family
+    pub artifact_types: Vec<String>,
+    pub ignore_artifact_types: Vec<String>,
+    /// What to do with each artifact type ("open", "save", "inline" or "ignore") when Tab opens
+    /// it, and right away when one arrives
+    pub artifact_actions: HashMap<String, ArtifactAction>,
+    /// Let the weather tool look up real conditions on wttr.in, sending it the location; mock
+    /// data otherwise
+    pub live_weather: bool,
+    /// Environment variables the system_info tool may show Claude; none by default
+    pub system_info_env: Vec<String>,
+    /// Tools implemented by programs on this machine
+    pub external_tools: Vec<ExternalTool>,
+    /// `serve` mode: requests answered at once, requests allowed to wait for a slot, and how
+    /// long they may wait before getting a 429
+    pub serve_max_concurrent: Option<usize>,
+    pub serve_max_queued: Option<usize>,
+    pub serve_queue_timeout_secs: Option<u64>,
+    /// Inputs remembered across runs for Shift+Up/Down recall; 0 keeps them for this run only
+    pub history_size: Option<usize>,
+    /// Names shown in the chat instead of "user" and "assistant"
+    pub user_label: Option<String>,
+    pub assistant_label: Option<String>,
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("claude-chatbot").join("config.toml"))
+    }
+
+    pub fn load() -> Result<Self> {
+        let Some(path) = Self::path().filter(|path| path.exists()) else {
+            return Ok(Self::default());
+        };
+
+        let contents = fs::read_to_string(&path)?;
+        let config: Self =
+            toml::from_str(&contents).map_err(|e| anyhow::anyhow!("Invalid config file {}: {}", path.display(), e))?;
+        Redactor::new(false, &config.redact_patterns)
+            .map_err(|e| anyhow::anyhow!("Invalid config file {}: {}", path.display(), e))?;
+        config.check().map_err(|e| anyhow::anyhow!("Invalid config file {}: {}", path.display(), e))?;
+        Ok(config)
+    }
+
+    /// Values the file's types allow but the API and the history window don't
+    fn check(&self) -> Result<()> {
+        if self.max_history == Some(0) {
+            return Err(anyhow::anyhow!("max_history must be at least 1"));
+        }
+        for (key, value) in [("temperature", self.temperature), ("top_p", self.top_p)] {
+            if let Some(value) = value.filter(|value| !(0.0..=1.0).contains(value)) {
+                return Err(anyhow::anyhow!("{} must be between 0 and 1, not {}", key, value));
+            }
+        }
+        Ok(())
+    }
+
+    /// Stores the API key at the top of the config file, ahead of any tables, keeping the rest
+    /// of the file as it was
+    pub fn save_api_key(api_key: &str) -> Result<PathBuf> {
+        let path = Self::path().ok_or_else(|| anyhow::anyhow!("Could not determine config directory"))?;
+        write_api_key(&path, api_key)?;
+        Ok(path)
+    }
+
+    pub fn model(&self) -> &str {
+        self.model.as_deref().unwrap_or(DEFAULT_MODEL)
+    }
+
+    /// Display name for a message role; the API always gets the canonical role
+    pub fn role_label<'a>(&'a self, role: &'a str) -> &'a str {
+        let label = match role {
+            "user" => self.user_label.as_deref(),
+            "assistant" => self.assistant_label.as_deref(),
+            _ => None,
+        };
+        label.unwrap_or(role)
+    }
+
+    pub fn max_tokens(&self) -> u32 {
+        self.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS)
+    }
+
+    /// Thinking budgets must be at least MIN_THINKING_BUDGET and leave room for the answer
+    pub fn check_thinking_budget(&self, budget: u32) -> Result<()> {
+        if budget < MIN_THINKING_BUDGET {
+            return Err(anyhow::anyhow!("The thinking budget must be at least {} tokens", MIN_THINKING_BUDGET));
+        }
+        if budget >= self.max_tokens() {
+            return Err(anyhow::anyhow!(
+                "The thinking budget must be below max_tokens ({}); raise --max-tokens first",
+                self.max_tokens()
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn max_tool_iterations(&self) -> u32 {
+        self.max_tool_iterations.unwrap_or(DEFAULT_MAX_TOOL_ITERATIONS)
+    }
+
+    pub fn history_size(&self) -> usize {
+        self.history_size.unwrap_or(DEFAULT_HISTORY_SIZE)
+    }
+
+    pub fn serve_max_concurrent(&self) -> usize {
+        self.serve_max_concurrent.unwrap_or(DEFAULT_SERVE_MAX_CONCURRENT).max(1)
+    }
+
+    pub fn serve_max_queued(&self) -> usize {
+        self.serve_max_queued.unwrap_or(DEFAULT_SERVE_MAX_QUEUED)
+    }
+
+    pub fn serve_queue_timeout(&self) -> Duration {
+        Duration::from_secs(self.serve_queue_timeout_secs.unwrap_or(DEFAULT_SERVE_QUEUE_TIMEOUT_SECS))
+    }
+
+    /// What to scrub from tool results, if anything; custom patterns were validated on load
+    pub fn redactor(&self) -> Option<Redactor> {
+        Redactor::new(self.redact_secrets, &self.redact_patterns)
+            .ok()
+            .filter(|redactor| !redactor.is_empty())
+    }
+
+    pub fn max_tool_result_chars(&self) -> usize {
+        self.max_tool_result_chars.unwrap_or(DEFAULT_MAX_TOOL_RESULT_CHARS)
+    }
+
+    pub fn number_precision(&self) -> usize {
+        self.number_precision.unwrap_or(DEFAULT_NUMBER_PRECISION)
+    }
+
+    pub fn color_level(&self) -> ColorLevel {
+        self.color.unwrap_or_else(ColorLevel::detect)
+    }
+
+    pub fn background(&self) -> Background {
+        self.background.unwrap_or_else(Background::detect)
+    }
+
+    /// The syntax theme for the current background
+    pub fn syntax_theme(&self) -> &str {
+        let configured = match self.background() {
+            Background::Light => self.light_theme.as_deref(),
+            Background::Dark => self.dark_theme.as_deref(),
+        };
+        configured.unwrap_or(self.background().default_theme())
+    }
+
+    pub fn api_version(&self) -> &str {
+        self.api_version.as_deref().unwrap_or(DEFAULT_API_VERSION)
+    }
+
+    pub fn parse_retries(&self) -> u32 {
+        self.parse_retries.unwrap_or(DEFAULT_PARSE_RETRIES)
+    }
+
+    /// Resolves a user-supplied path against the output directory; absolute paths are kept as-is
+    pub fn output_path(&self, path: &str) -> PathBuf {
+        let path = expand_home(Path::new(path));
+        match &self.output_dir {
+            Some(base) if path.is_relative() => expand_home(base).join(path),
+            _ => path,
+        }
+    }
+}
+
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// The file holds a secret from here on, so on Unix only its owner may read it, whether it is
+/// created here or already existed
+fn write_api_key(path: &Path, api_key: &str) -> Result<()> {
+    let existing = fs::read_to_string(path).unwrap_or_default();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        if path.exists() {
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    let mut file = options.open(path)?;
+    let line = format!("api_key = {}\n", toml::Value::String(api_key.to_string()));
+    file.write_all((line + &existing).as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_file_sets_the_color_level() {
+        let config: Config = toml::from_str("color = \"256\"").unwrap();
+        assert_eq!(config.color_level(), ColorLevel::Ansi256);
+        assert!(toml::from_str::<Config>("color = \"1024\"").is_err());
+    }
+
+    #[test]
+    fn sampling_settings_outside_zero_to_one_are_rejected() {
+        let check = |toml: &str| toml::from_str::<Config>(toml).unwrap().check();
+        assert!(check("temperature = 0.7\ntop_p = 1.0").is_ok());
+        let error = check("temperature = 1.5").unwrap_err().to_string();
+        assert!(error.contains("temperature"), "{}", error);
+        let error = check("top_p = -0.1").unwrap_err().to_string();
+        assert!(error.contains("top_p"), "{}", error);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn api_key_file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        let created = dir.path().join("claude-chatbot").join("config.toml");
+        write_api_key(&created, "sk-test").unwrap();
+        assert_eq!(mode(&created), 0o600);
+
+        let existing = dir.path().join("existing.toml");
+        std::fs::write(&existing, "model = \"claude\"\n").unwrap();
+        std::fs::set_permissions(&existing, std::fs::Permissions::from_mode(0o644)).unwrap();
+        write_api_key(&existing, "sk-test").unwrap();
+        assert_eq!(mode(&existing), 0o600);
+        let config: Config = toml::from_str(&std::fs::read_to_string(&existing).unwrap()).unwrap();
+        assert_eq!(config.api_key.as_deref(), Some("sk-test"));
+        assert_eq!(config.model.as_deref(), Some("claude"));
+    }
+}
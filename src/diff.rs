@@ -0,0 +1,42 @@
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use similar::udiff::UnifiedHunkHeader;
+use similar::{ChangeTag, TextDiff};
+
+const CONTEXT_LINES: usize = 3;
+
+/// Colored unified diff between the current and proposed contents of a file. A file that
+/// doesn't exist yet (`old` is `None`) is shown entirely as additions
+pub fn render_diff(old: Option<&str>, new: &str) -> Vec<Line<'static>> {
+    let Some(old) = old else {
+        return new.lines().map(|line| added(line.to_string())).collect();
+    };
+
+    let diff = TextDiff::from_lines(old, new);
+    let mut lines = Vec::new();
+    for group in diff.grouped_ops(CONTEXT_LINES) {
+        lines.push(Line::from(Span::styled(
+            UnifiedHunkHeader::new(&group).to_string(),
+            Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD),
+        )));
+        for op in &group {
+            for change in diff.iter_changes(op) {
+                let text = change.value().trim_end_matches(['\n', '\r']).to_string();
+                lines.push(match change.tag() {
+                    ChangeTag::Insert => added(text),
+                    ChangeTag::Delete => Line::from(Span::styled(format!("-{}", text), Style::default().fg(Color::Red))),
+                    ChangeTag::Equal => Line::from(format!(" {}", text)),
+                });
+            }
+        }
+    }
+
+    if lines.is_empty() {
+        lines.push(Line::from(Span::styled("(no changes)", Style::default().fg(Color::DarkGray))));
+    }
+    lines
+}
+
+fn added(text: String) -> Line<'static> {
+    Line::from(Span::styled(format!("+{}", text), Style::default().fg(Color::Green)))
+}
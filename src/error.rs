@@ -0,0 +1,74 @@
+use std::time::Duration;
+use thiserror::Error;
+
+/// Failures callers may want to tell apart, e.g. to retry a rate limit but not a bad key. The
+/// messages match what the binary has always printed, so `/why` keeps recognising them
+#[derive(Debug, Error)]
+pub enum ChatError {
+    /// The API answered with an error status other than the ones below
+    #[error("API error: {message}")]
+    Api { status: u16, message: String },
+    /// An `error` event partway through a streamed response, with its `type` (e.g.
+    /// `overloaded_error`); there is no HTTP status since the response had already started
+    #[error("API error: {message}")]
+    Stream { kind: String, message: String },
+    /// The API couldn't be reached, or the connection dropped mid-stream
+    #[error(transparent)]
+    Network(#[from] reqwest::Error),
+    /// A 429, with how long the API asked us to wait when it said
+    #[error("API error: {message}")]
+    RateLimited { retry_after: Option<Duration>, message: String },
+    /// A missing, invalid or under-privileged API key (401 or 403)
+    #[error("API error: {message}")]
+    Auth { message: String },
+    /// A tool failed to run
+    #[error("{0}")]
+    Tool(String),
+    /// A response or recording that couldn't be understood
+    #[error("{0}")]
+    Parse(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl ChatError {
+    /// Classifies an unsuccessful HTTP response by its status
+    pub fn from_status(status: u16, retry_after: Option<Duration>, message: String) -> Self {
+        match status {
+            401 | 403 => ChatError::Auth { message },
+            429 => ChatError::RateLimited { retry_after, message },
+            _ => ChatError::Api { status, message },
+        }
+    }
+
+    /// Classifies an `error` event that arrived mid-stream by its `type`
+    pub fn from_stream_error(kind: String, message: String) -> Self {
+        match kind.as_str() {
+            "authentication_error" | "permission_error" => ChatError::Auth { message },
+            "rate_limit_error" => ChatError::RateLimited { retry_after: None, message },
+            _ => ChatError::Stream { kind, message },
+        }
+    }
+}
+
+impl From<serde_json::Error> for ChatError {
+    fn from(e: serde_json::Error) -> Self {
+        ChatError::Parse(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stream_errors_keep_their_type() {
+        let error = ChatError::from_stream_error("overloaded_error".to_string(), "Overloaded".to_string());
+        assert!(matches!(&error, ChatError::Stream { kind, .. } if kind == "overloaded_error"));
+        assert_eq!(error.to_string(), "API error: Overloaded");
+        assert!(matches!(
+            ChatError::from_stream_error("rate_limit_error".to_string(), String::new()),
+            ChatError::RateLimited { retry_after: None, .. }
+        ));
+    }
+}
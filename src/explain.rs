@@ -0,0 +1,90 @@
+/// A known kind of failure: any of `patterns` appearing in the error text (case-insensitive)
+/// identifies it
+struct Rule {
+    patterns: &'static [&'static str],
+    cause: &'static str,
+    fix: &'static str,
+}
+
+// Checked in order, so more specific rules come before general ones
+const RULES: &[Rule] = &[
+    Rule {
+        patterns: &["authentication_error", "invalid x-api-key", "api key required"],
+        cause: "The API key is missing or was rejected.",
+        fix: "Check that ANTHROPIC_API_KEY (or --api-key) is set to a current key from console.anthropic.com.",
+    },
+    Rule {
+        patterns: &["credit balance", "billing"],
+        cause: "The account has run out of credit.",
+        fix: "Add credit or check the plan under Billing at console.anthropic.com.",
+    },
+    Rule {
+        patterns: &["permission_error"],
+        cause: "The API key isn't allowed to use this model or feature.",
+        fix: "Try another --model, or check the key's workspace permissions.",
+    },
+    Rule {
+        patterns: &["rate_limit_error", "rate limit"],
+        cause: "Too many requests or tokens were sent in a short time.",
+        fix: "Wait a minute, then /resend. Long conversations use up the token limit faster.",
+    },
+    Rule {
+        patterns: &["overloaded_error", "overloaded"],
+        cause: "The API is temporarily overloaded.",
+        fix: "Wait a little and /resend; this is not a problem with your request.",
+    },
+    Rule {
+        patterns: &["prompt is too long", "context window", "context length", "too many tokens"],
+        cause: "The conversation no longer fits in the model's context window.",
+        fix: "Start a fresh conversation with Ctrl+N, /undo large turns, or /detach big attachments.",
+    },
+    Rule {
+        patterns: &["max_tokens"],
+        cause: "The requested maximum response length isn't accepted by this model.",
+        fix: "Lower --max-tokens (or max_tokens in the config file).",
+    },
+    Rule {
+        patterns: &["not_found_error", "model:"],
+        cause: "The model name wasn't recognised.",
+        fix: "Check --model or ANTHROPIC_MODEL, or use an alias like sonnet with /retry.",
+    },
+    Rule {
+        patterns: &["expected an event stream"],
+        cause: "Something other than the API answered, often a proxy or captive portal, or an outage page.",
+        fix: "Check your network or proxy settings, then /resend.",
+    },
+    Rule {
+        patterns: &["error sending request", "dns error", "connection", "timed out"],
+        cause: "The API couldn't be reached.",
+        fix: "Check your internet connection and any HTTPS_PROXY setting, then /resend.",
+    },
+    Rule {
+        patterns: &["declined this file write"],
+        cause: "You rejected a file write Claude proposed.",
+        fix: "Nothing to fix; tell Claude what to change if you want a different edit.",
+    },
+    Rule {
+        patterns: &["declined this tool call"],
+        cause: "You rejected a tool call Claude proposed.",
+        fix: "Nothing to fix; tell Claude what to do instead if you want a different approach.",
+    },
+    Rule {
+        patterns: &["no such file", "not found"],
+        cause: "A file or directory involved doesn't exist.",
+        fix: "Check the path; relative paths are resolved from the directory the chatbot was started in.",
+    },
+    Rule {
+        patterns: &["permission denied"],
+        cause: "The operating system refused access to a file.",
+        fix: "Check the file's permissions, or pick a location you can write to.",
+    },
+];
+
+/// A plain-language cause and likely fix for an error message, if it matches a known failure
+pub fn explain_error(error: &str) -> Option<(&'static str, &'static str)> {
+    let error = error.to_lowercase();
+    RULES
+        .iter()
+        .find(|rule| rule.patterns.iter().any(|pattern| error.contains(pattern)))
+        .map(|rule| (rule.cause, rule.fix))
+}
@@ -0,0 +1,139 @@
+use anyhow::Result;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+use crate::api::{ContentBlock, Message, MessageContent, ToolResultContent};
+use crate::markdown::strip_escapes;
+
+pub fn to_markdown(title: &str, messages: &[Message], notes: &[String]) -> String {
+    let mut output = format!("# {}\n", if title.is_empty() { "Conversation" } else { title });
+
+    // Results arrive in the following user turn; each is shown under the call that asked for it
+    let results: HashMap<&str, (&ToolResultContent, bool)> = messages
+        .iter()
+        .filter_map(|message| match &message.content {
+            MessageContent::Blocks(blocks) => Some(blocks),
+            MessageContent::Text(_) => None,
+        })
+        .flatten()
+        .filter_map(|block| match block {
+            ContentBlock::ToolResult { tool_use_id, content, is_error } => Some((tool_use_id.as_str(), (content, *is_error))),
+            _ => None,
+        })
+        .collect();
+
+    for message in messages {
+        let heading = if message.role == "user" { "User" } else { "Claude" };
+        let body = match &message.content {
+            MessageContent::Text(text) => text.trim_end().to_string(),
+            MessageContent::Blocks(blocks) => blocks
+                .iter()
+                .filter_map(|block| match block {
+                    ContentBlock::Text { text } => Some(text.trim_end().to_string()),
+                    ContentBlock::ToolUse { id, name, input } => Some(tool_section(name, input, results.get(id.as_str()))),
+                    _ => None,
+                })
+                .filter(|part| !part.trim().is_empty())
+                .collect::<Vec<_>>()
+                .join("\n\n"),
+        };
+        if body.trim().is_empty() {
+            continue;
+        }
+        output.push_str(&format!("\n## {}\n\n{}\n", heading, body));
+    }
+
+    if !notes.is_empty() {
+        output.push_str("\n## Notes\n\n");
+        for note in notes {
+            output.push_str(&format!("- {}\n", note));
+        }
+    }
+
+    // Tool output may carry terminal colors, which don't belong in a file
+    strip_escapes(&output)
+}
+
+/// A labeled tool call with its input and, once it ran, its result
+fn tool_section(name: &str, input: &Value, result: Option<&(&ToolResultContent, bool)>) -> String {
+    let input = serde_json::to_string_pretty(input).unwrap_or_else(|_| input.to_string());
+    let result = match result {
+        Some((content, false)) => format!("**Result:**\n\n{}", fence("", &content.to_text())),
+        Some((content, true)) => format!("**Result (error):**\n\n{}", fence("", &content.to_text())),
+        None => "*No result recorded*".to_string(),
+    };
+    format!("### Tool: {}\n\n**Input:**\n\n{}\n\n{}", name, fence("json", &input), result)
+}
+
+/// A fenced code block long enough that backticks in `text` can't close it early
+fn fence(lang: &str, text: &str) -> String {
+    let longest_run = text
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or(0);
+    let ticks = "`".repeat(longest_run.max(2) + 1);
+    format!("{}{}\n{}\n{}", ticks, lang, text.trim_end(), ticks)
+}
+
+/// The conversation as an object with its title, messages and notes
+pub fn to_json(title: &str, messages: &[Message], notes: &[String]) -> Result<String> {
+    Ok(serde_json::to_string_pretty(&json!({
+        "title": title,
+        "messages": messages,
+        "notes": notes,
+    }))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(role: &str, text: &str) -> Message {
+        Message {
+            role: role.to_string(),
+            content: MessageContent::Text(text.to_string()),
+            stop_reason: None,
+            model: None,
+        }
+    }
+
+    #[test]
+    fn json_export_includes_notes() {
+        let messages = [message("user", "Hello"), message("assistant", "Hi")];
+        let notes = ["Follow up on the invoice".to_string()];
+        let exported: Value = serde_json::from_str(&to_json("Greeting", &messages, &notes).unwrap()).unwrap();
+        assert_eq!(exported["title"], "Greeting");
+        assert_eq!(exported["messages"][1]["content"], "Hi");
+        assert_eq!(exported["notes"], json!(["Follow up on the invoice"]));
+    }
+
+    #[test]
+    fn tool_turns_export_as_sections_with_input_and_result() {
+        let call = Message {
+            content: MessageContent::Blocks(vec![
+                ContentBlock::Text { text: "Let me check.".to_string() },
+                ContentBlock::ToolUse {
+                    id: "call_1".to_string(),
+                    name: "search_files".to_string(),
+                    input: json!({ "pattern": "fn main" }),
+                },
+            ]),
+            ..message("assistant", "")
+        };
+        let result = Message {
+            content: MessageContent::Blocks(vec![ContentBlock::ToolResult {
+                tool_use_id: "call_1".to_string(),
+                content: ToolResultContent::Text("src/main.rs:1: ```fn main()```".to_string()),
+                is_error: false,
+            }]),
+            ..message("user", "")
+        };
+
+        let markdown = to_markdown("", &[message("user", "Where is main?"), call, result], &[]);
+        let expected = "# Conversation\n\n## User\n\nWhere is main?\n\n## Claude\n\nLet me check.\n\n\
+            ### Tool: search_files\n\n**Input:**\n\n```json\n{\n  \"pattern\": \"fn main\"\n}\n```\n\n\
+            **Result:**\n\n````\nsrc/main.rs:1: ```fn main()```\n````\n";
+        assert_eq!(markdown, expected);
+    }
+}
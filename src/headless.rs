@@ -0,0 +1,154 @@
+use anyhow::Result;
+
+use crate::api::{
+    ClaudeClient, ContentBlock, Message, MessageContent, MessageRequest, RateLimits, ResponseContent,
+    ToolResultContent, Usage,
+};
+use crate::config::Config;
+use crate::mcp::{McpHandler, ToolLog};
+
+/// The outcome of a prompt run without the TUI
+pub struct Completion {
+    pub text: String,
+    pub stop_reason: Option<String>,
+    /// Summed over every round of the tool loop
+    pub usage: Usage,
+    /// As reported by the last response
+    pub rate_limits: RateLimits,
+}
+
+/// The read-only tools offered when running without the TUI
+pub fn tool_handler(config: &Config, tool_log: Option<ToolLog>) -> McpHandler {
+    let mut mcp_handler = McpHandler::new(true)
+        .with_precision(config.number_precision())
+        .with_live_weather(config.live_weather)
+        .with_external_tools(config.external_tools.clone())
+        .with_redactor(config.redactor())
+        .with_exposed_env(config.system_info_env.clone())
+        .with_max_result_chars(config.max_tool_result_chars());
+    if let Some(tool_log) = tool_log {
+        mcp_handler.set_tool_log(tool_log);
+    }
+    mcp_handler
+}
+
+/// `--print`: answers a single prompt on stdout. With `show_prompt`, the system prompt and
+/// message as sent go to stderr first, so stdout still pipes cleanly
+pub async fn print(
+    client: ClaudeClient,
+    config: Config,
+    tool_log: Option<ToolLog>,
+    prompt: String,
+    system: Option<String>,
+    show_prompt: bool,
+) -> Result<()> {
+    if show_prompt {
+        eprintln!("--- system ---\n{}", system.as_deref().unwrap_or("(none)"));
+        eprintln!("--- user ---\n{}\n---", prompt);
+    }
+    let mcp_handler = tool_handler(&config, tool_log);
+    let completion = complete(&client, &mcp_handler, &config, prompt, system.as_deref()).await?;
+    println!("{}", completion.text);
+    Ok(())
+}
+
+/// Runs one prompt to completion, answering tool calls along the way. Shared by `serve` and
+/// `--batch`, where nobody is around to approve side effects, so only read-only tools are
+/// offered
+pub async fn complete(
+    client: &ClaudeClient,
+    mcp_handler: &McpHandler,
+    config: &Config,
+    prompt: String,
+    system: Option<&str>,
+) -> Result<Completion> {
+    let mut messages = vec![Message {
+        role: "user".to_string(),
+        content: MessageContent::Text(prompt),
+        stop_reason: None,
+        model: None,
+    }];
+    let mut usage = Usage::default();
+
+    let mut rounds = 0;
+    loop {
+        let mut builder = MessageRequest::builder()
+            .model(config.model())
+            .max_tokens(config.max_tokens())
+            .cache_conversation(config.cache_conversation)
+            .messages(messages.clone());
+        if !config.no_tools {
+            builder = builder.tools(mcp_handler.tools());
+        }
+        if let Some(system) = system {
+            builder = builder.system(system);
+        }
+        if let Some(budget) = config.thinking_budget {
+            builder = builder.thinking_budget(budget);
+        }
+        if let Some(temperature) = config.temperature {
+            builder = builder.temperature(temperature);
+        }
+        if let Some(top_p) = config.top_p {
+            builder = builder.top_p(top_p);
+        }
+        if let Some(service_tier) = &config.service_tier {
+            builder = builder.service_tier(service_tier.clone());
+        }
+
+        let mut stream = client.stream_message(builder.build()).await?;
+        while stream.next_delta().await?.is_some() {}
+        let rate_limits = stream.rate_limits().clone();
+        let response = stream.into_response();
+        usage.add(&response.usage);
+
+        let mut blocks = Vec::new();
+        let mut results = Vec::new();
+        let mut text = String::new();
+        let mut stopped = false;
+        for content in response.content {
+            match content {
+                ResponseContent::Text { text: part } => {
+                    text.push_str(&part);
+                    blocks.push(ContentBlock::Text { text: part });
+                }
+                ResponseContent::ToolUse { id, name, input } => {
+                    stopped |= config.stop_tools.contains(&name);
+                    let (content, is_error) = match mcp_handler.handle_tool_call(&name, &input).await {
+                        Ok(result) => (ToolResultContent::from_output(result), false),
+                        Err(e) => (format!("Error: {}", e).into(), true),
+                    };
+                    if let Some(failure) = mcp_handler.take_log_failure() {
+                        eprintln!("Warning: {}", failure);
+                    }
+                    results.push(ContentBlock::ToolResult { tool_use_id: id.clone(), content, is_error });
+                    blocks.push(ContentBlock::ToolUse { id, name, input });
+                }
+                thinking => blocks.extend(thinking.thinking_block()),
+            }
+        }
+
+        rounds += 1;
+        if results.is_empty() || stopped || rounds > config.max_tool_iterations() {
+            return Ok(Completion {
+                text,
+                stop_reason: response.stop_reason,
+                usage,
+                rate_limits,
+            });
+        }
+
+        messages.push(Message {
+            role: "assistant".to_string(),
+            content: MessageContent::Blocks(blocks),
+            stop_reason: None,
+            model: None,
+        });
+        messages.push(Message {
+            role: "user".to_string(),
+            content: MessageContent::Blocks(results),
+            stop_reason: None,
+            model: None,
+        });
+    }
+}
@@ -0,0 +1,97 @@
+use anyhow::Result;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Everything typed into the input box, oldest first, kept on disk between runs like a shell
+/// history. Each entry is stored as a JSON string on its own line so newlines survive
+pub struct InputHistory {
+    entries: Vec<String>,
+    path: Option<PathBuf>,
+    max_entries: usize,
+    /// Position while stepping through entries, and what was typed before recall began
+    cursor: Option<usize>,
+    draft: String,
+}
+
+impl InputHistory {
+    /// Loads up to `max_entries` from the state directory; 0 keeps history in memory only
+    pub fn load(max_entries: usize) -> Self {
+        let path = (max_entries > 0)
+            .then(|| dirs::state_dir().or_else(dirs::data_local_dir))
+            .flatten()
+            .map(|dir| dir.join("claude-chatbot").join("history"));
+        let entries: Vec<String> = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+        let mut history = Self {
+            entries,
+            path,
+            max_entries,
+            cursor: None,
+            draft: String::new(),
+        };
+        history.trim();
+        history
+    }
+
+    /// Records a sent input, unless it repeats the previous one
+    pub fn push(&mut self, entry: &str) -> Result<()> {
+        self.cursor = None;
+        if entry.trim().is_empty() || self.entries.last().is_some_and(|last| last == entry) {
+            return Ok(());
+        }
+        self.entries.push(entry.to_string());
+
+        let Some(path) = self.path.clone() else { return Ok(()) };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Appending keeps writes cheap; the file is rewritten once it is well over the cap
+        if self.entries.len() > self.max_entries * 2 {
+            self.trim();
+            let contents: String = self.entries.iter().map(|entry| format!("{}\n", serde_json::json!(entry))).collect();
+            fs::write(&path, contents)?;
+        } else {
+            let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+            writeln!(file, "{}", serde_json::json!(entry))?;
+        }
+        Ok(())
+    }
+
+    /// Steps back to an older entry, remembering `current` to come back to
+    pub fn previous(&mut self, current: &str) -> Option<String> {
+        let index = match self.cursor {
+            Some(0) => return None,
+            Some(index) => index - 1,
+            None => {
+                self.draft = current.to_string();
+                self.entries.len().checked_sub(1)?
+            }
+        };
+        self.cursor = Some(index);
+        Some(self.entries[index].clone())
+    }
+
+    /// Steps forward to a newer entry, ending back at what was being typed
+    pub fn next(&mut self) -> Option<String> {
+        let index = self.cursor? + 1;
+        if index < self.entries.len() {
+            self.cursor = Some(index);
+            Some(self.entries[index].clone())
+        } else {
+            self.cursor = None;
+            Some(std::mem::take(&mut self.draft))
+        }
+    }
+
+    fn trim(&mut self) {
+        if self.max_entries > 0 && self.entries.len() > self.max_entries {
+            self.entries.drain(..self.entries.len() - self.max_entries);
+        }
+    }
+}
@@ -1,37 +1,434 @@
 use anyhow::Result;
-use clap::Parser;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser};
+use reqwest::header::{HeaderName, HeaderValue};
 use std::env;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
 
 mod api;
+mod color;
+mod commands;
+mod config;
+mod diff;
+mod error;
+mod explain;
+mod export;
+mod headless;
+mod history;
 mod ui;
 mod artifacts;
+mod attachments;
+mod batch;
 mod mcp;
+mod redact;
 mod markdown;
+mod math;
+mod server;
+mod session;
+mod wrap;
 
-use api::ClaudeClient;
+use api::{ClaudeClient, Recording};
+use color::{Background, ColorLevel};
+use config::Config;
+use mcp::ToolLog;
+use session::{SessionLog, SessionStore};
 use ui::ChatApp;
 
+/// Combined size of --file contents; files past it are skipped with a warning
+const MAX_FILE_CONTEXT_BYTES: usize = 200_000;
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Subcommand>,
+
+    /// Message to start with; it is placed in the input box for editing unless --send is given
+    prompt: Option<String>,
+
+    /// Send the initial prompt immediately
+    #[arg(long, requires = "prompt")]
+    send: bool,
+
+    /// Answer the prompt on stdout and exit instead of starting the TUI; only read-only tools are available
+    #[arg(short, long, requires = "prompt")]
+    print: bool,
+
+    /// With --print, also write the system prompt and message as sent to stderr
+    #[arg(long, requires = "print")]
+    show_prompt: bool,
+
+    /// Send each line of this file (or stdin for -) as a separate prompt and print the results
+    /// as JSON lines instead of starting the TUI; only read-only tools are available
+    #[arg(long, conflicts_with = "prompt")]
+    batch: Option<PathBuf>,
+
     /// Anthropic API key (or set ANTHROPIC_API_KEY environment variable)
     #[arg(short, long)]
     api_key: Option<String>,
+
+    /// Never prompt on the terminal, e.g. for an API key on first run
+    #[arg(long)]
+    no_interactive: bool,
+
+    /// Model to use
+    #[arg(short, long, env = "ANTHROPIC_MODEL")]
+    model: Option<String>,
+
+    /// Maximum tokens per response; values above the standard limit enable the long-output beta
+    #[arg(long)]
+    max_tokens: Option<u32>,
+
+    /// Sampling temperature between 0 and 1
+    #[arg(long, env = "ANTHROPIC_TEMPERATURE", value_parser = unit_interval)]
+    temperature: Option<f32>,
+
+    /// Nucleus sampling cutoff between 0 and 1; usually set this or --temperature, not both
+    #[arg(long, env = "ANTHROPIC_TOP_P", value_parser = unit_interval)]
+    top_p: Option<f32>,
+
+    /// Enable extended thinking with this many tokens to reason with (at least 1024, below --max-tokens)
+    #[arg(long)]
+    thinking_budget: Option<u32>,
+
+    /// Send only the last N messages to the API (plus the system prompt), keeping the rest on screen
+    #[arg(long, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    max_history: Option<usize>,
+
+    /// Maximum rounds of tool calls per message before stopping to ask the user (default 10)
+    #[arg(long)]
+    max_tool_iterations: Option<u32>,
+
+    /// End the tool loop as soon as Claude calls this tool, instead of sending its result back (repeatable)
+    #[arg(long = "stop-on-tool", value_name = "TOOL")]
+    stop_tools: Vec<String>,
+
+    /// Value for the anthropic-version header, to pin or upgrade the API version
+    #[arg(long)]
+    api_version: Option<String>,
+
+    /// Service tier to request: auto uses priority capacity when available, standard_only never does
+    #[arg(long, value_parser = ["auto", "standard_only"])]
+    service_tier: Option<String>,
+
+    /// System prompt text; may be repeated and mixed with --system-file, joined in the given order
+    #[arg(long)]
+    system: Vec<String>,
+
+    /// File to read part of the system prompt from; may be repeated
+    #[arg(long)]
+    system_file: Vec<PathBuf>,
+
+    /// File to include as context at the start of the conversation; may be repeated
+    #[arg(long)]
+    file: Vec<PathBuf>,
+
+    /// Colors to use for code highlighting; detected from COLORTERM and TERM by default
+    #[arg(long, value_enum)]
+    color: Option<ColorLevel>,
+
+    /// Terminal background, which picks the syntax theme and UI colors; detected from COLORFGBG by default
+    #[arg(long, value_enum)]
+    background: Option<Background>,
+
+    /// Only enable read-only tools without side effects
+    #[arg(long)]
+    safe: bool,
+
+    /// Don't offer Claude any tools, for plain chat
+    #[arg(long)]
+    no_tools: bool,
+
+    /// Replace API keys, tokens and other secrets in tool results with [REDACTED]
+    #[arg(long)]
+    redact_secrets: bool,
+
+    /// Mark the conversation history as a prompt cache breakpoint so repeated turns are cheaper
+    #[arg(long)]
+    cache_conversation: bool,
+
+    /// Start in the dense layout, without blank lines between messages (toggle with Ctrl+D)
+    #[arg(long)]
+    compact: bool,
+
+    /// Continue the saved session with this id (see /sessions)
+    #[arg(long)]
+    resume_id: Option<String>,
+
+    /// Append every message of this run to a JSONL transcript shared across runs
+    #[arg(long)]
+    append_session: Option<PathBuf>,
+
+    /// Base directory for exports and saved sessions; relative paths are resolved against it
+    #[arg(long)]
+    output_dir: Option<PathBuf>,
+
+    /// Append a JSON line per tool call (time, tool, input, result or error, duration) to this file
+    #[arg(long)]
+    tool_log: Option<PathBuf>,
+
+    /// Extra HTTP header for every API request, as "Name: Value"; may be repeated. These are
+    /// applied last, so they replace built-in headers of the same name
+    #[arg(long = "header", value_name = "HEADER", value_parser = parse_header)]
+    headers: Vec<(HeaderName, HeaderValue)>,
+
+    /// Write every request and its response to this directory as JSON pairs
+    #[arg(long, conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Serve responses previously captured with --record instead of calling the API
+    #[arg(long)]
+    replay: Option<PathBuf>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Subcommand {
+    /// Run a local HTTP server answering POST /chat with {"prompt": "..."} instead of the TUI.
+    /// Only read-only tools are available since nobody is there to approve side effects.
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        bind: SocketAddr,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args = Args::parse();
+    // Variables from a project-local .env file fill in whatever the real environment leaves unset
+    if let Err(e) = dotenvy::dotenv() {
+        if !e.not_found() {
+            eprintln!("Warning: failed to load .env file: {}", e);
+        }
+    }
+
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches)?;
+    let system_prompt = compose_system_prompt(&args, &matches)?;
     
-    let api_key = args.api_key
+    let mut config = Config::load()?;
+    let api_key = match args.api_key
         .or_else(|| env::var("ANTHROPIC_API_KEY").ok())
-        .ok_or_else(|| anyhow::anyhow!("API key required. Use --api-key or set ANTHROPIC_API_KEY"))?;
+        .or_else(|| config.api_key.clone())
+        .or_else(|| args.replay.as_ref().map(|_| String::new()))
+    {
+        Some(api_key) => api_key,
+        None if !args.no_interactive && io::stdin().is_terminal() => setup_api_key()?,
+        None => return Err(anyhow::anyhow!("API key required. Use --api-key or set ANTHROPIC_API_KEY")),
+    };
+
+    config.safe |= args.safe;
+    config.no_tools |= args.no_tools;
+    config.cache_conversation |= args.cache_conversation;
+    config.redact_secrets |= args.redact_secrets;
+    config.compact |= args.compact;
+    if args.model.is_some() {
+        config.model = args.model;
+    }
+    if args.max_tokens.is_some() {
+        config.max_tokens = args.max_tokens;
+    }
+    if args.temperature.is_some() {
+        config.temperature = args.temperature;
+    }
+    if args.top_p.is_some() {
+        config.top_p = args.top_p;
+    }
+    if args.thinking_budget.is_some() {
+        config.thinking_budget = args.thinking_budget;
+    }
+    if let Some(budget) = config.thinking_budget {
+        config.check_thinking_budget(budget)?;
+    }
+    if args.max_history.is_some() {
+        config.max_history = args.max_history;
+    }
+    if args.max_tool_iterations.is_some() {
+        config.max_tool_iterations = args.max_tool_iterations;
+    }
+    config.stop_tools.extend(args.stop_tools);
+    if args.api_version.is_some() {
+        config.api_version = args.api_version;
+    }
+    if args.service_tier.is_some() {
+        config.service_tier = args.service_tier;
+    }
+    if args.color.is_some() {
+        config.color = args.color;
+    }
+    if args.background.is_some() {
+        config.background = args.background;
+    }
+    if !markdown::theme_exists(config.syntax_theme()) {
+        return Err(anyhow::anyhow!(
+            "Unknown syntax theme {}; available: {}",
+            config.syntax_theme(),
+            markdown::theme_names().join(", ")
+        ));
+    }
+    if args.output_dir.is_some() {
+        config.output_dir = args.output_dir;
+    }
+
+    let mut client = ClaudeClient::new(api_key)
+        .with_api_version(config.api_version())
+        .with_parse_retries(config.parse_retries())
+        .with_headers(args.headers.into_iter().collect());
+    if let Some(dir) = args.record {
+        client = client.with_recording(Recording::Record(dir));
+    } else if let Some(dir) = args.replay {
+        client = client.with_recording(Recording::Replay(dir));
+    }
 
-    let client = ClaudeClient::new(api_key);
-    let mut app = ChatApp::new(client);
+    let tool_log = args.tool_log.as_deref().map(ToolLog::open).transpose()?;
+
+    if let Some(Subcommand::Serve { bind }) = args.command {
+        return server::serve(bind, client, config, tool_log).await;
+    }
+    if let Some(path) = &args.batch {
+        let system = system_prompt.map(|(prompt, _)| prompt);
+        return batch::run(path, client, config, tool_log, system).await;
+    }
+    if args.print {
+        let system = system_prompt.map(|(prompt, _)| prompt);
+        let prompt = args.prompt.unwrap_or_default();
+        return headless::print(client, config, tool_log, prompt, system, args.show_prompt).await;
+    }
+
+    let mut app = ChatApp::new(client, config.clone());
+    if let Some(id) = &args.resume_id {
+        app.resume(SessionStore::new(&config)?.load(id)?);
+    }
+    if let Some((prompt, files)) = system_prompt {
+        app.set_system_prompt(prompt, &files);
+    }
+    if let Some(tool_log) = tool_log {
+        app.set_tool_log(tool_log);
+    }
+    if let Some(path) = &args.append_session {
+        app.set_session_log(SessionLog::open(path)?);
+    }
+    if let Some(prompt) = args.prompt {
+        app.set_initial_prompt(prompt, args.send);
+    }
     
     app.run().await?;
     
     Ok(())
 }
 
+/// First-run setup: asks for the API key and offers to keep it in the config file. The prompts
+/// go to stderr so they don't end up in piped output
+fn setup_api_key() -> Result<String> {
+    eprintln!("No Anthropic API key found (--api-key, ANTHROPIC_API_KEY or the config file).");
+    eprintln!("Create one at https://console.anthropic.com/settings/keys");
+    let api_key = ask("API key: ")?;
+    if api_key.is_empty() {
+        return Err(anyhow::anyhow!("API key required. Use --api-key or set ANTHROPIC_API_KEY"));
+    }
+
+    let location = Config::path().map_or_else(|| "the config file".to_string(), |path| path.display().to_string());
+    eprintln!("Saving the key stores it in plain text in {}.", location);
+    if ask("Save it for next time? [y/N] ")?.eq_ignore_ascii_case("y") {
+        let path = Config::save_api_key(&api_key)?;
+        eprintln!("Saved to {}", path.display());
+    }
+    Ok(api_key)
+}
+
+fn ask(prompt: &str) -> Result<String> {
+    eprint!("{}", prompt);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim().to_string())
+}
+
+fn unit_interval(value: &str) -> Result<f32, String> {
+    let number: f32 = value.parse().map_err(|_| format!("{} is not a number", value))?;
+    if (0.0..=1.0).contains(&number) {
+        Ok(number)
+    } else {
+        Err(format!("{} is not between 0 and 1", number))
+    }
+}
+
+fn parse_header(value: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let (name, header_value) = value
+        .split_once(':')
+        .ok_or_else(|| format!("{} is not in \"Name: Value\" form", value))?;
+    let name = HeaderName::from_bytes(name.trim().as_bytes())
+        .map_err(|_| format!("{} is not a valid header name", name.trim()))?;
+    let header_value = HeaderValue::from_str(header_value.trim())
+        .map_err(|_| format!("{} is not a valid header value", header_value.trim()))?;
+    Ok((name, header_value))
+}
+
+/// Joins the --system values and --system-file contents in command line order, separated by
+/// blank lines, followed by any --file context. Returns the prompt along with the files it was
+/// read from
+fn compose_system_prompt(args: &Args, matches: &ArgMatches) -> Result<Option<(String, Vec<PathBuf>)>> {
+    let mut parts: Vec<(usize, String)> = Vec::new();
+    if let Some(indices) = matches.indices_of("system") {
+        parts.extend(indices.zip(args.system.iter().cloned()));
+    }
+    if let Some(indices) = matches.indices_of("system_file") {
+        for (index, path) in indices.zip(&args.system_file) {
+            let text = fs::read_to_string(path)
+                .map_err(|e| anyhow::anyhow!("Failed to read system prompt file {}: {}", path.display(), e))?;
+            parts.push((index, text.trim().to_string()));
+        }
+    }
+    parts.sort_by_key(|(index, _)| *index);
+
+    let mut files = args.system_file.clone();
+    if let Some((context, context_files)) = file_context(&args.file)? {
+        parts.push((usize::MAX, context));
+        files.extend(context_files);
+    }
+    if parts.is_empty() {
+        return Ok(None);
+    }
+
+    let prompt = parts
+        .into_iter()
+        .map(|(_, part)| part)
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    Ok(Some((prompt, files)))
+}
+
+/// Reads the --file paths into one block with a header per file, stopping at
+/// MAX_FILE_CONTEXT_BYTES. Returns the block and the files that made it in
+fn file_context(paths: &[PathBuf]) -> Result<Option<(String, Vec<PathBuf>)>> {
+    let mut sections = Vec::new();
+    let mut included = Vec::new();
+    let mut total = 0;
+    for path in paths {
+        let text = fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read context file {}: {}", path.display(), e))?;
+        if total + text.len() > MAX_FILE_CONTEXT_BYTES {
+            eprintln!(
+                "Warning: skipping {} ({} bytes) - context files are limited to {} bytes in total",
+                path.display(),
+                text.len(),
+                MAX_FILE_CONTEXT_BYTES
+            );
+            continue;
+        }
+        total += text.len();
+        sections.push(format!("<file path=\"{}\">\n{}\n</file>", path.display(), text.trim_end()));
+        included.push(path.clone());
+    }
+    if sections.is_empty() {
+        return Ok(None);
+    }
+
+    let context = format!(
+        "The user has shared the following files for this conversation:\n\n{}",
+        sections.join("\n\n")
+    );
+    Ok(Some((context, included)))
+}
@@ -1,13 +1,31 @@
 use anyhow::Result;
 use pulldown_cmark::{Parser, Event, Tag, CodeBlockKind};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span, Text};
 use syntect::easy::HighlightLines;
-use syntect::highlighting::{ThemeSet, Style};
+use syntect::highlighting::ThemeSet;
 use syntect::parsing::SyntaxSet;
-use syntect::util::{as_24_bit_terminal_escaped, LinesWithEndings};
+use syntect::util::LinesWithEndings;
+use unicode_width::UnicodeWidthStr;
+
+use crate::color::{Background, ColorLevel};
+use crate::math::prepare as prepare_math;
+use crate::wrap::{fit, Overflow};
+
+const DEFINITION_INDENT: &str = "    ";
 
 pub struct MarkdownRenderer {
     syntax_set: SyntaxSet,
     theme_set: ThemeSet,
+    code_labels: bool,
+    line_numbers: bool,
+    color_level: ColorLevel,
+    code_overflow: Overflow,
+    theme: String,
+    unicode_math: bool,
+    /// Color of line numbers, code labels and other chrome around the content
+    gutter_color: Color,
+    inline_code_color: Color,
 }
 
 impl MarkdownRenderer {
@@ -15,60 +33,302 @@ impl MarkdownRenderer {
         Self {
             syntax_set: SyntaxSet::load_defaults_newlines(),
             theme_set: ThemeSet::load_defaults(),
+            code_labels: false,
+            line_numbers: false,
+            color_level: ColorLevel::TrueColor,
+            code_overflow: Overflow::Wrap,
+            theme: Background::Dark.default_theme().to_string(),
+            unicode_math: false,
+            gutter_color: Color::DarkGray,
+            inline_code_color: Color::Yellow,
         }
     }
 
+    /// The syntect theme code blocks are highlighted with; must be one of `theme_names`
+    pub fn with_theme(mut self, theme: impl Into<String>) -> Self {
+        self.theme = theme.into();
+        self
+    }
+
+    /// Whether code lines wider than the pane wrap or scroll horizontally; prose always wraps
+    pub fn with_code_overflow(mut self, code_overflow: Overflow) -> Self {
+        self.code_overflow = code_overflow;
+        self
+    }
+
+    /// Downsamples highlighting colors for terminals without truecolor support
+    pub fn with_color_level(mut self, color_level: ColorLevel) -> Self {
+        self.color_level = color_level;
+        self
+    }
+
+    /// Puts a dim label with the language above fenced code blocks
+    pub fn with_code_labels(mut self, code_labels: bool) -> Self {
+        self.code_labels = code_labels;
+        self
+    }
+
+    /// Writes simple LaTeX math with Unicode symbols instead of showing the source
+    pub fn with_unicode_math(mut self, unicode_math: bool) -> Self {
+        self.unicode_math = unicode_math;
+        self
+    }
+
+    /// Numbers the lines of code blocks in a dim gutter
+    pub fn with_line_numbers(mut self, line_numbers: bool) -> Self {
+        self.line_numbers = line_numbers;
+        self
+    }
+
+    /// Color of line numbers, code labels and other chrome, e.g. from the background's palette
+    pub fn with_gutter_color(mut self, gutter_color: Color) -> Self {
+        self.gutter_color = gutter_color;
+        self
+    }
+
+    /// Color of `inline code` spans
+    pub fn with_inline_code_color(mut self, inline_code_color: Color) -> Self {
+        self.inline_code_color = inline_code_color;
+        self
+    }
+
+    /// Renders to a string with ANSI escape codes, for output outside the TUI
     pub fn render(&self, markdown: &str) -> Result<String> {
-        let mut output = String::new();
-        let parser = Parser::new(markdown);
+        let lines = self.render_lines(markdown, None, 0)?;
+        Ok(to_ansi(&lines, self.color_level))
+    }
+
+    /// Renders to formatted plain text with no escape codes, for files that must stay clean.
+    /// Escape sequences already in the text, e.g. from a tool's colored output, are dropped too
+    pub fn render_plain(&self, markdown: &str) -> Result<String> {
+        let lines = self.render_lines(markdown, None, 0)?;
+        Ok(lines
+            .iter()
+            .map(|line| strip_escapes(&line.spans.iter().map(|span| span.content.as_ref()).collect::<String>()))
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    /// Renders to styled ratatui text that widgets can display directly. Code lines longer than
+    /// `width` are fitted here, since breaking them at spaces like prose would misplace code;
+    /// with horizontal scrolling, `pan` is how many columns they are scrolled by
+    pub fn render_to_text(&self, markdown: &str, width: usize, pan: usize) -> Result<Text<'static>> {
+        Ok(Text::from(self.render_lines(markdown, Some(width), pan)?))
+    }
+
+    /// How far code blocks rendered at `width` can be scrolled sideways before the end of their
+    /// widest line is in view; panning further would only show blank columns
+    pub fn max_code_pan(&self, markdown: &str, width: usize) -> usize {
+        let markdown = prepare_math(markdown, self.unicode_math);
+        let mut code: Option<String> = None;
+        let mut max_pan = 0;
+        for event in Parser::new(&markdown) {
+            match event {
+                Event::Start(Tag::CodeBlock(_)) => code = Some(String::new()),
+                Event::Text(text) => {
+                    if let Some(code) = code.as_mut() {
+                        code.push_str(&text);
+                    }
+                }
+                Event::End(Tag::CodeBlock(_)) => {
+                    let code = code.take().unwrap_or_default();
+                    let number_width = code.lines().count().to_string().len();
+                    let gutter = if self.line_numbers { number_width + 3 } else { 0 };
+                    let visible = width.saturating_sub(gutter).max(1);
+                    let widest = code.lines().map(UnicodeWidthStr::width).max().unwrap_or(0);
+                    max_pan = max_pan.max(widest.saturating_sub(visible));
+                }
+                _ => {}
+            }
+        }
+        max_pan
+    }
+
+    /// Renders a partially streamed message: complete blocks get full markdown rendering while
+    /// the trailing incomplete block (e.g. an unclosed code fence) is shown as plain text
+    pub fn render_streaming(&self, text: &str, width: usize) -> Result<Text<'static>> {
+        let (complete, pending) = text.split_at(stream_boundary(text));
+        let mut lines = self.render_lines(complete, Some(width), 0)?;
+        if pending.trim().is_empty() {
+            return Ok(Text::from(lines));
+        }
+        if !lines.is_empty() {
+            lines.push(Line::default());
+        }
+
+        // A code block still being streamed is highlighted as far as it has got, so it doesn't
+        // flip from plain to highlighted when the closing fence arrives
+        let (prose, open_code) = match open_fence(pending) {
+            Some((start, lang, code)) => (&pending[..start], Some((lang, code))),
+            None => (pending, None),
+        };
+        lines.extend(prose.trim_end().lines().map(|line| Line::from(line.to_string())));
+        if let Some((lang, code)) = open_code {
+            lines.extend(self.code_block_lines(code, lang, Some(width), 0)?);
+            lines.push(Line::from(Span::styled("…", self.gutter_style())));
+        }
+        Ok(Text::from(lines))
+    }
+
+    fn render_lines(&self, markdown: &str, width: Option<usize>, pan: usize) -> Result<Vec<Line<'static>>> {
+        let mut lines = Vec::new();
+        let mut current: Vec<Span<'static>> = Vec::new();
+        // Each nested Strong/Emphasis pushes the combined style of everything enclosing it
+        let mut style_stack = vec![Style::default()];
+        let mut list_stack: Vec<Option<u64>> = Vec::new();
         let mut in_code_block = false;
         let mut code_lang = String::new();
         let mut code_content = String::new();
+        // pulldown-cmark has no definition lists, so `Term` followed by a `: definition` line of
+        // the same paragraph is recognised here instead
+        let mut paragraph_line: Option<usize> = None;
+        let mut line_is_definition = false;
+        let mut previous_was_definition = false;
 
-        for event in parser {
+        let markdown = prepare_math(markdown, self.unicode_math);
+        for event in Parser::new(&markdown) {
+            let style = *style_stack.last().unwrap_or(&Style::default());
             match event {
-                Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(lang))) => {
+                Event::Start(Tag::CodeBlock(kind)) => {
+                    flush_line(&mut lines, &mut current);
                     in_code_block = true;
-                    code_lang = lang.to_string();
+                    code_lang = match kind {
+                        CodeBlockKind::Fenced(lang) => lang.to_string(),
+                        CodeBlockKind::Indented => String::new(),
+                    };
                     code_content.clear();
                 }
-                Event::End(Tag::CodeBlock(CodeBlockKind::Fenced(_))) => {
+                Event::End(Tag::CodeBlock(_)) => {
                     in_code_block = false;
-                    let highlighted = self.highlight_code(&code_content, &code_lang)?;
-                    output.push_str(&highlighted);
-                    output.push('\n');
+                    lines.extend(self.code_block_lines(&code_content, &code_lang, width, pan)?);
+                    push_blank(&mut lines);
                 }
                 Event::Text(text) => {
                     if in_code_block {
                         code_content.push_str(&text);
+                    } else if let Some(definition) = text
+                        .strip_prefix(": ")
+                        .filter(|_| current.is_empty() && paragraph_line.is_some_and(|line| line > 0))
+                    {
+                        if !previous_was_definition {
+                            if let Some(term) = lines.last_mut() {
+                                embolden(term);
+                            }
+                        }
+                        line_is_definition = true;
+                        current.push(Span::raw(DEFINITION_INDENT));
+                        current.push(Span::styled(definition.to_string(), style));
                     } else {
-                        output.push_str(&text);
+                        current.push(Span::styled(text.to_string(), style));
                     }
                 }
+                Event::Code(code) => {
+                    current.push(Span::styled(code.to_string(), style.patch(self.inline_code_style())));
+                }
                 Event::Start(Tag::Heading(level, _, _)) => {
-                    output.push_str(&format!("\n{} ", "#".repeat(level as usize)));
+                    flush_line(&mut lines, &mut current);
+                    if !lines.is_empty() {
+                        push_blank(&mut lines);
+                    }
+                    let heading_style = style.add_modifier(Modifier::BOLD);
+                    style_stack.push(heading_style);
+                    current.push(Span::styled(format!("{} ", "#".repeat(level as usize)), heading_style));
                 }
                 Event::End(Tag::Heading(_, _, _)) => {
-                    output.push('\n');
+                    style_stack.pop();
+                    flush_line(&mut lines, &mut current);
+                    push_blank(&mut lines);
                 }
-                Event::Start(Tag::Emphasis) => output.push_str("*"),
-                Event::End(Tag::Emphasis) => output.push_str("*"),
-                Event::Start(Tag::Strong) => output.push_str("**"),
-                Event::End(Tag::Strong) => output.push_str("**"),
-                Event::Code(code) => {
-                    output.push_str(&format!("`{}`", code));
+                Event::Start(Tag::Emphasis) => style_stack.push(style.add_modifier(Modifier::ITALIC)),
+                Event::Start(Tag::Strong) => style_stack.push(style.add_modifier(Modifier::BOLD)),
+                Event::End(Tag::Emphasis) | Event::End(Tag::Strong) => {
+                    style_stack.pop();
+                }
+                Event::Start(Tag::List(start)) => {
+                    flush_line(&mut lines, &mut current);
+                    list_stack.push(start);
+                }
+                Event::End(Tag::List(_)) => {
+                    list_stack.pop();
+                    if list_stack.is_empty() {
+                        push_blank(&mut lines);
+                    }
+                }
+                Event::Start(Tag::Item) => {
+                    flush_line(&mut lines, &mut current);
+                    let indent = "  ".repeat(list_stack.len().saturating_sub(1));
+                    let marker = match list_stack.last_mut() {
+                        Some(Some(number)) => {
+                            let marker = format!("{}. ", number);
+                            *number += 1;
+                            marker
+                        }
+                        _ => "• ".to_string(),
+                    };
+                    current.push(Span::raw(format!("{}{}", indent, marker)));
+                }
+                Event::End(Tag::Item) => flush_line(&mut lines, &mut current),
+                Event::Start(Tag::Paragraph) => {
+                    paragraph_line = Some(0);
+                    previous_was_definition = false;
+                }
+                Event::End(Tag::Paragraph) => {
+                    paragraph_line = None;
+                    line_is_definition = false;
+                    flush_line(&mut lines, &mut current);
+                    if list_stack.is_empty() {
+                        push_blank(&mut lines);
+                    }
+                }
+                Event::SoftBreak | Event::HardBreak => {
+                    flush_line(&mut lines, &mut current);
+                    paragraph_line = paragraph_line.map(|line| line + 1);
+                    previous_was_definition = std::mem::take(&mut line_is_definition);
                 }
-                Event::SoftBreak | Event::HardBreak => output.push('\n'),
                 _ => {}
             }
         }
 
-        Ok(output)
+        flush_line(&mut lines, &mut current);
+        while lines.last().is_some_and(|line| line.spans.is_empty()) {
+            lines.pop();
+        }
+
+        Ok(lines)
+    }
+
+    /// A highlighted code block with its optional language label and line numbers
+    fn code_block_lines(&self, code: &str, lang: &str, width: Option<usize>, pan: usize) -> Result<Vec<Line<'static>>> {
+        let mut lines = Vec::new();
+        if self.code_labels && !lang.is_empty() {
+            lines.push(Line::from(Span::styled(format!("─ {}", lang), self.gutter_style())));
+        }
+        let code_lines = self.highlight_code(code, lang)?;
+        let number_width = code_lines.len().to_string().len();
+        let gutter = if self.line_numbers { number_width + 3 } else { 0 };
+        let rows = code_lines.into_iter().map(|line| match width {
+            Some(width) => fit(line, width.saturating_sub(gutter).max(1), self.code_overflow, pan),
+            None => vec![line],
+        });
+        if self.line_numbers {
+            lines.extend(number_lines(rows, number_width, self.gutter_style()));
+        } else {
+            lines.extend(rows.flatten());
+        }
+        Ok(lines)
+    }
+
+    fn gutter_style(&self) -> Style {
+        Style::default().fg(self.gutter_color)
     }
 
-    fn highlight_code(&self, code: &str, lang: &str) -> Result<String> {
+    fn inline_code_style(&self) -> Style {
+        Style::default().fg(self.inline_code_color)
+    }
+
+    fn highlight_code(&self, code: &str, lang: &str) -> Result<Vec<Line<'static>>> {
         if lang.is_empty() {
-            return Ok(code.to_string());
+            return Ok(code.lines().map(|line| Line::from(line.to_string())).collect());
         }
 
         let syntax = self.syntax_set
@@ -76,17 +336,338 @@ impl MarkdownRenderer {
             .or_else(|| self.syntax_set.find_syntax_by_extension(lang))
             .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text());
 
-        let theme = &self.theme_set.themes["base16-ocean.dark"];
+        let theme = self
+            .theme_set
+            .themes
+            .get(&self.theme)
+            .ok_or_else(|| anyhow::anyhow!("Unknown syntax theme {}", self.theme))?;
         let mut highlighter = HighlightLines::new(syntax, theme);
-        let mut output = String::new();
+        let mut output = Vec::new();
 
         for line in LinesWithEndings::from(code) {
-            let ranges: Vec<(Style, &str)> = highlighter.highlight_line(line, &self.syntax_set)?;
-            let escaped = as_24_bit_terminal_escaped(&ranges[..], false);
-            output.push_str(&escaped);
+            let ranges = highlighter.highlight_line(line, &self.syntax_set)?;
+            let spans: Vec<Span<'static>> = ranges
+                .into_iter()
+                .filter_map(|(style, text)| {
+                    let text = text.trim_end_matches('\n');
+                    if text.is_empty() {
+                        return None;
+                    }
+                    let fg = style.foreground;
+                    let span_style = match self.color_level.adapt(Color::Rgb(fg.r, fg.g, fg.b)) {
+                        Some(color) => Style::default().fg(color),
+                        None => Style::default(),
+                    };
+                    Some(Span::styled(text.to_string(), span_style))
+                })
+                .collect();
+            output.push(Line::from(spans));
         }
 
         Ok(output)
     }
 }
 
+/// The syntax themes bundled with syntect, for `light_theme` and `dark_theme`
+pub fn theme_names() -> Vec<String> {
+    ThemeSet::load_defaults().themes.into_keys().collect()
+}
+
+pub fn theme_exists(name: &str) -> bool {
+    ThemeSet::load_defaults().themes.contains_key(name)
+}
+
+/// Byte offset up to which streamed markdown consists only of finished blocks: the last
+/// blank line or closing code fence that isn't inside an open fence
+fn stream_boundary(text: &str) -> usize {
+    let mut boundary = 0;
+    let mut offset = 0;
+    let mut in_fence = false;
+
+    for line in text.split_inclusive('\n') {
+        offset += line.len();
+        if !line.ends_with('\n') {
+            break;
+        }
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+            if !in_fence {
+                boundary = offset;
+            }
+        } else if !in_fence && trimmed.is_empty() {
+            boundary = offset;
+        }
+    }
+
+    boundary
+}
+
+/// Finds a code fence that is opened but not yet closed, returning where its fence line starts,
+/// its language and the code received so far
+fn open_fence(text: &str) -> Option<(usize, &str, &str)> {
+    let mut offset = 0;
+    let mut open = None;
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            open = match open {
+                Some(_) => None,
+                None => Some((offset, trimmed[3..].trim(), offset + line.len())),
+            };
+        }
+        offset += line.len();
+    }
+    open.map(|(start, lang, code_start)| (start, lang, &text[code_start.min(text.len())..]))
+}
+
+/// Prefixes each source line's rows with a gutter, numbering only the first row so wrapped
+/// continuations are easy to tell apart from new lines
+fn number_lines(
+    rows: impl Iterator<Item = Vec<Line<'static>>>,
+    width: usize,
+    style: Style,
+) -> impl Iterator<Item = Line<'static>> {
+    rows.enumerate().flat_map(move |(index, rows)| {
+        rows.into_iter().enumerate().map(move |(row, mut line)| {
+            let gutter = if row == 0 {
+                format!("{:>width$} │ ", index + 1)
+            } else {
+                format!("{:>width$} ┆ ", "")
+            };
+            line.spans.insert(0, Span::styled(gutter, style));
+            line
+        })
+    })
+}
+
+/// Removes terminal escape sequences and other control characters, keeping newlines and tabs
+pub fn strip_escapes(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => match chars.next() {
+                // CSI, e.g. colors: parameters up to a final byte in @..~
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                // OSC, e.g. hyperlinks: up to BEL or ESC \\
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' || (c == '\x1b' && chars.next().is_some()) {
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            },
+            '\n' | '\t' => output.push(c),
+            c if c.is_control() => {}
+            c => output.push(c),
+        }
+    }
+    output
+}
+
+fn embolden(line: &mut Line<'static>) {
+    for span in &mut line.spans {
+        span.style = span.style.add_modifier(Modifier::BOLD);
+    }
+}
+
+fn flush_line(lines: &mut Vec<Line<'static>>, current: &mut Vec<Span<'static>>) {
+    if !current.is_empty() {
+        lines.push(Line::from(std::mem::take(current)));
+    }
+}
+
+fn push_blank(lines: &mut Vec<Line<'static>>) {
+    if lines.last().is_some_and(|line| !line.spans.is_empty()) {
+        lines.push(Line::default());
+    }
+}
+
+fn to_ansi(lines: &[Line], color_level: ColorLevel) -> String {
+    let mut output = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        if i > 0 {
+            output.push('\n');
+        }
+        for span in &line.spans {
+            let codes = sgr_codes(span.style, color_level);
+            if codes.is_empty() {
+                output.push_str(&span.content);
+            } else {
+                output.push_str(&format!("\x1b[{}m{}\x1b[0m", codes.join(";"), span.content));
+            }
+        }
+    }
+    output
+}
+
+fn sgr_codes(style: Style, color_level: ColorLevel) -> Vec<String> {
+    let mut codes = Vec::new();
+    if style.add_modifier.contains(Modifier::BOLD) {
+        codes.push("1".to_string());
+    }
+    if style.add_modifier.contains(Modifier::ITALIC) {
+        codes.push("3".to_string());
+    }
+    if style.add_modifier.contains(Modifier::UNDERLINED) {
+        codes.push("4".to_string());
+    }
+    if let Some(fg) = style.fg.and_then(|color| color_level.adapt(color)).and_then(|color| ansi_color(color, 30)) {
+        codes.push(fg);
+    }
+    if let Some(bg) = style.bg.and_then(|color| color_level.adapt(color)).and_then(|color| ansi_color(color, 40)) {
+        codes.push(bg);
+    }
+    codes
+}
+
+/// SGR parameters for a color, where `base` is 30 for foreground or 40 for background. The
+/// 16 named colors use the basic codes that even 16-color terminals understand
+fn ansi_color(color: Color, base: u8) -> Option<String> {
+    let index = match color {
+        Color::Rgb(r, g, b) => return Some(format!("{};2;{};{};{}", base + 8, r, g, b)),
+        Color::Indexed(index) => return Some(format!("{};5;{}", base + 8, index)),
+        Color::Black => 0,
+        Color::Red => 1,
+        Color::Green => 2,
+        Color::Yellow => 3,
+        Color::Blue => 4,
+        Color::Magenta => 5,
+        Color::Cyan => 6,
+        Color::Gray => 7,
+        Color::DarkGray => 8,
+        Color::LightRed => 9,
+        Color::LightGreen => 10,
+        Color::LightYellow => 11,
+        Color::LightBlue => 12,
+        Color::LightMagenta => 13,
+        Color::LightCyan => 14,
+        Color::White => 15,
+        Color::Reset => return None,
+    };
+    Some(if index < 8 {
+        (base + index).to_string()
+    } else {
+        (base + 60 + index - 8).to_string()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans(markdown: &str) -> Vec<Span<'static>> {
+        MarkdownRenderer::new()
+            .render_lines(markdown, None, 0)
+            .unwrap()
+            .into_iter()
+            .flat_map(|line| line.spans)
+            .collect()
+    }
+
+    fn span<'a>(spans: &'a [Span<'static>], text: &str) -> &'a Span<'static> {
+        spans.iter().find(|span| span.content == text).unwrap()
+    }
+
+    #[test]
+    fn nested_emphasis_combines_modifiers() {
+        let spans = spans("**_bold italic_**");
+        let modifiers = span(&spans, "bold italic").style.add_modifier;
+        assert!(modifiers.contains(Modifier::BOLD | Modifier::ITALIC));
+    }
+
+    #[test]
+    fn inline_code_inside_bold_keeps_both_styles() {
+        let spans = spans("**see `main` here**");
+        let code = span(&spans, "main").style;
+        assert!(code.add_modifier.contains(Modifier::BOLD));
+        assert_eq!(code.fg, Some(Color::Yellow));
+        let text = span(&spans, "see ").style;
+        assert!(text.add_modifier.contains(Modifier::BOLD));
+        assert_eq!(text.fg, None);
+    }
+
+    fn text(line: &Line) -> String {
+        line.spans.iter().map(|span| span.content.as_ref()).collect()
+    }
+
+    #[test]
+    fn code_lines_are_numbered_right_aligned() {
+        let code: Vec<String> = (1..=10).map(|n| format!("line {}", n)).collect();
+        let markdown = format!("```\n{}\n```", code.join("\n"));
+        let lines = MarkdownRenderer::new()
+            .with_line_numbers(true)
+            .render_lines(&markdown, None, 0)
+            .unwrap();
+        let texts: Vec<String> = lines.iter().map(text).collect();
+        assert_eq!(texts.len(), 10);
+        assert_eq!(texts[0], " 1 │ line 1");
+        assert_eq!(texts[8], " 9 │ line 9");
+        assert_eq!(texts[9], "10 │ line 10");
+        assert_eq!(lines[0].spans[0].style, Style::default().fg(Color::DarkGray));
+    }
+
+    #[test]
+    fn long_code_lines_wrap_under_a_single_number() {
+        let long = "x".repeat(500);
+        let markdown = format!("```\n{}\nshort\n```", long);
+        let lines = MarkdownRenderer::new()
+            .with_line_numbers(true)
+            .render_lines(&markdown, Some(54), 0)
+            .unwrap();
+        let texts: Vec<String> = lines.iter().map(text).collect();
+        // 54 columns less the 4-column gutter leaves 50 per row
+        assert_eq!(texts.len(), 11);
+        assert_eq!(texts[0], format!("1 │ {}", "x".repeat(50)));
+        assert!(texts[1..10].iter().all(|text| text == &format!("  ┆ {}", "x".repeat(50))));
+        assert_eq!(texts[10], "2 │ short");
+    }
+
+    #[test]
+    fn plain_rendering_has_no_escape_bytes() {
+        let markdown = "# Title\n\n**bold** and `code`\n\n```rust\nfn main() {}\n```\n\n\x1b[31mred\x1b[0m \x1b]8;;https://example.com\x07link\x1b]8;;\x07";
+        let plain = MarkdownRenderer::new().render_plain(markdown).unwrap();
+        assert!(!plain.bytes().any(|byte| byte == 0x1b || byte == 0x07), "{:?}", plain);
+        assert!(plain.contains("fn main() {}"));
+        assert!(plain.contains("red link"));
+    }
+
+    #[test]
+    fn definition_lists_indent_definitions_under_a_bold_term() {
+        let lines = MarkdownRenderer::new()
+            .render_lines("Term\n: First definition\n: Second definition\n\nPlain paragraph", None, 0)
+            .unwrap();
+        let texts: Vec<String> = lines.iter().map(text).collect();
+        assert_eq!(
+            texts,
+            ["Term", "    First definition", "    Second definition", "", "Plain paragraph"]
+        );
+        assert!(lines[0].spans[0].style.add_modifier.contains(Modifier::BOLD));
+        assert!(!lines[1].spans[1].style.add_modifier.contains(Modifier::BOLD));
+    }
+
+    #[test]
+    fn a_colon_line_without_a_term_is_not_a_definition() {
+        let lines = MarkdownRenderer::new().render_lines(": not a definition", None, 0).unwrap();
+        assert_eq!(lines.iter().map(text).collect::<Vec<_>>(), [": not a definition"]);
+    }
+
+    #[test]
+    fn code_pan_stops_at_the_widest_line() {
+        let markdown = format!("```\n{}\n{}\n```\n\n{}", "a".repeat(30), "b".repeat(100), "c".repeat(300));
+        let renderer = MarkdownRenderer::new();
+        assert_eq!(renderer.max_code_pan(&markdown, 40), 60);
+        assert_eq!(renderer.max_code_pan(&markdown, 120), 0);
+        // The line number gutter takes 4 columns for a 2-line block
+        assert_eq!(renderer.with_line_numbers(true).max_code_pan(&markdown, 40), 64);
+    }
+}
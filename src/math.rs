@@ -0,0 +1,433 @@
+/// Keeps LaTeX math in `$...$` and `$$...$$` intact through markdown parsing, which would
+/// otherwise read `_` and `*` inside it as emphasis. With `unicode`, simple expressions are
+/// rewritten with Unicode symbols, superscripts and subscripts instead; anything the small
+/// substitution table can't express is kept as the original LaTeX. Fenced, indented and
+/// inline code is left alone.
+pub fn prepare(markdown: &str, unicode: bool) -> String {
+    let mut output = String::with_capacity(markdown.len());
+    let mut prose = String::new();
+    let mut fence: Option<&str> = None;
+    // An indented line is code only after a blank line and outside a list, where it would
+    // otherwise continue a paragraph or a list item
+    let mut after_blank = true;
+    let mut in_list = false;
+    let mut in_indented_code = false;
+    for line in markdown.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        let blank = trimmed.trim_end().is_empty();
+        let indented = !blank && indent(line) >= 4;
+        if let Some(marker) = fence {
+            output.push_str(line);
+            if trimmed.starts_with(marker) {
+                fence = None;
+            }
+        } else if indented && (in_indented_code || (after_blank && !in_list)) {
+            if !in_indented_code {
+                output.push_str(&prepare_prose(&prose, unicode));
+                prose.clear();
+                in_indented_code = true;
+            }
+            output.push_str(line);
+        } else if in_indented_code && blank {
+            output.push_str(line);
+        } else if !indented && (trimmed.starts_with("```") || trimmed.starts_with("~~~")) {
+            output.push_str(&prepare_prose(&prose, unicode));
+            prose.clear();
+            output.push_str(line);
+            fence = Some(&trimmed[..3]);
+            in_indented_code = false;
+        } else {
+            in_indented_code = false;
+            if !indented && is_list_item(trimmed) {
+                in_list = true;
+            } else if !indented && !blank && after_blank {
+                in_list = false;
+            }
+            prose.push_str(line);
+        }
+        after_blank = blank;
+    }
+    output.push_str(&prepare_prose(&prose, unicode));
+    output
+}
+
+/// Leading whitespace in columns, with tabs stopping at multiples of 4
+fn indent(line: &str) -> usize {
+    let mut columns = 0;
+    for c in line.chars() {
+        match c {
+            ' ' => columns += 1,
+            '\t' => columns += 4 - columns % 4,
+            _ => break,
+        }
+    }
+    columns
+}
+
+/// Whether a line opens a bullet or numbered list item
+fn is_list_item(line: &str) -> bool {
+    let marker = line.trim_start_matches(|c: char| c.is_ascii_digit());
+    let rest = if marker.len() < line.len() {
+        marker.strip_prefix(['.', ')'])
+    } else {
+        line.strip_prefix(['-', '*', '+'])
+    };
+    rest.is_some_and(|rest| rest.is_empty() || rest.starts_with([' ', '\t', '\n']))
+}
+
+/// Markdown outside fenced code: inline code spans are copied as they are and math is escaped
+fn prepare_prose(text: &str, unicode: bool) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(['`', '$', '\\']) {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if rest.starts_with('\\') {
+            let escaped = rest.chars().nth(1).map_or(1, |c| 1 + c.len_utf8());
+            output.push_str(&rest[..escaped]);
+            rest = &rest[escaped..];
+        } else if rest.starts_with('`') {
+            let ticks = rest.len() - rest.trim_start_matches('`').len();
+            let end = closing_ticks(&rest[ticks..], ticks).map_or(ticks, |end| ticks + end + ticks);
+            output.push_str(&rest[..end]);
+            rest = &rest[end..];
+        } else if let Some(body) = rest.strip_prefix("$$") {
+            match body.find("$$").filter(|&end| !body[..end].trim().is_empty()) {
+                Some(end) => {
+                    output.push_str(&render_math(&body[..end], "$$", unicode));
+                    rest = &body[end + 2..];
+                }
+                None => {
+                    output.push_str("$$");
+                    rest = body;
+                }
+            }
+        } else {
+            match inline_math_end(&rest[1..]) {
+                Some(end) => {
+                    output.push_str(&render_math(&rest[1..end + 1], "$", unicode));
+                    rest = &rest[end + 2..];
+                }
+                None => {
+                    output.push('$');
+                    rest = &rest[1..];
+                }
+            }
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+/// Where a code span opened with `ticks` backticks closes: the next run of exactly that many
+fn closing_ticks(text: &str, ticks: usize) -> Option<usize> {
+    let mut offset = 0;
+    while let Some(start) = text[offset..].find('`') {
+        let start = offset + start;
+        let run = text[start..].len() - text[start..].trim_start_matches('`').len();
+        if run == ticks {
+            return Some(start);
+        }
+        offset = start + run;
+    }
+    None
+}
+
+/// The closing `$` of inline math on the same line. As in Pandoc, the math may not start or end
+/// with a space and the closing `$` may not be followed by a digit, so prices like "$5 and $10"
+/// stay as they are
+fn inline_math_end(text: &str) -> Option<usize> {
+    if text.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let line = &text[..text.find('\n').unwrap_or(text.len())];
+    let mut previous = None;
+    for (index, c) in line.char_indices() {
+        if c == '$' && index > 0 && !previous.is_some_and(|p: char| p.is_whitespace() || p == '\\') {
+            let next = line[index + 1..].chars().next();
+            return (!next.is_some_and(|next| next.is_ascii_digit())).then_some(index);
+        }
+        previous = Some(c);
+    }
+    None
+}
+
+/// Math as markdown that renders literally: converted to Unicode when asked and possible,
+/// otherwise the LaTeX with its delimiters
+fn render_math(math: &str, delimiter: &str, unicode: bool) -> String {
+    let text = unicode
+        .then(|| to_unicode(math))
+        .flatten()
+        .unwrap_or_else(|| format!("{}{}{}", delimiter, math, delimiter));
+    // Every ASCII punctuation character may be backslash-escaped in CommonMark
+    text.chars().fold(String::with_capacity(text.len()), |mut escaped, c| {
+        if c.is_ascii_punctuation() {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+        escaped
+    })
+}
+
+fn to_unicode(math: &str) -> Option<String> {
+    let mut output = String::new();
+    let mut chars = math.trim().chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                let name: String = std::iter::from_fn(|| chars.next_if(char::is_ascii_alphabetic)).collect();
+                if name.is_empty() {
+                    match chars.next()? {
+                        ',' | ';' | ':' | ' ' => output.push(' '),
+                        '{' => output.push('{'),
+                        '}' => output.push('}'),
+                        '%' => output.push('%'),
+                        '$' => output.push('$'),
+                        _ => return None,
+                    }
+                    continue;
+                }
+                match name.as_str() {
+                    "frac" => {
+                        let numerator = to_unicode(&group(&mut chars)?)?;
+                        let denominator = to_unicode(&group(&mut chars)?)?;
+                        output.push_str(&format!("{}/{}", parenthesize(&numerator), parenthesize(&denominator)));
+                    }
+                    "sqrt" => {
+                        let radicand = to_unicode(&group(&mut chars)?)?;
+                        output.push('√');
+                        output.push_str(&parenthesize(&radicand));
+                    }
+                    "left" | "right" => {}
+                    _ => output.push_str(symbol(&name)?),
+                }
+            }
+            '^' | '_' => {
+                let script = to_unicode(&group(&mut chars)?)?;
+                let map = if c == '^' { superscript } else { subscript };
+                for c in script.chars() {
+                    output.push(map(c)?);
+                }
+            }
+            '{' | '}' => {}
+            c => output.push(c),
+        }
+    }
+    Some(output)
+}
+
+/// A `{...}` group, or the single character standing in for one
+fn group(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<String> {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    if chars.next_if_eq(&'{').is_none() {
+        return chars.next().map(String::from);
+    }
+    let mut depth = 0;
+    let mut group = String::new();
+    for c in chars.by_ref() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(group),
+            '}' => depth -= 1,
+            _ => {}
+        }
+        group.push(c);
+    }
+    None
+}
+
+fn parenthesize(text: &str) -> String {
+    if text.chars().count() == 1 {
+        text.to_string()
+    } else {
+        format!("({})", text)
+    }
+}
+
+fn symbol(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "alpha" => "α",
+        "beta" => "β",
+        "gamma" => "γ",
+        "delta" => "δ",
+        "epsilon" | "varepsilon" => "ε",
+        "zeta" => "ζ",
+        "eta" => "η",
+        "theta" => "θ",
+        "iota" => "ι",
+        "kappa" => "κ",
+        "lambda" => "λ",
+        "mu" => "μ",
+        "nu" => "ν",
+        "xi" => "ξ",
+        "pi" => "π",
+        "rho" => "ρ",
+        "sigma" => "σ",
+        "tau" => "τ",
+        "phi" | "varphi" => "φ",
+        "chi" => "χ",
+        "psi" => "ψ",
+        "omega" => "ω",
+        "Gamma" => "Γ",
+        "Delta" => "Δ",
+        "Theta" => "Θ",
+        "Lambda" => "Λ",
+        "Pi" => "Π",
+        "Sigma" => "Σ",
+        "Phi" => "Φ",
+        "Psi" => "Ψ",
+        "Omega" => "Ω",
+        "cdot" => "·",
+        "times" => "×",
+        "div" => "÷",
+        "pm" => "±",
+        "mp" => "∓",
+        "le" | "leq" => "≤",
+        "ge" | "geq" => "≥",
+        "ne" | "neq" => "≠",
+        "approx" => "≈",
+        "equiv" => "≡",
+        "sim" => "∼",
+        "propto" => "∝",
+        "infty" => "∞",
+        "partial" => "∂",
+        "nabla" => "∇",
+        "sum" => "∑",
+        "prod" => "∏",
+        "int" => "∫",
+        "in" => "∈",
+        "notin" => "∉",
+        "subset" => "⊂",
+        "subseteq" => "⊆",
+        "cup" => "∪",
+        "cap" => "∩",
+        "emptyset" => "∅",
+        "forall" => "∀",
+        "exists" => "∃",
+        "neg" => "¬",
+        "land" | "wedge" => "∧",
+        "lor" | "vee" => "∨",
+        "to" | "rightarrow" => "→",
+        "leftarrow" => "←",
+        "Rightarrow" | "implies" => "⇒",
+        "Leftrightarrow" | "iff" => "⇔",
+        "mapsto" => "↦",
+        "ldots" | "dots" | "cdots" => "…",
+        "circ" => "∘",
+        "degree" => "°",
+        "quad" | "qquad" => " ",
+        // Function names print as themselves
+        "sin" => "sin",
+        "cos" => "cos",
+        "tan" => "tan",
+        "log" => "log",
+        "ln" => "ln",
+        "exp" => "exp",
+        "lim" => "lim",
+        "max" => "max",
+        "min" => "min",
+        _ => return None,
+    })
+}
+
+fn superscript(c: char) -> Option<char> {
+    Some(match c {
+        '0' => '⁰',
+        '1' => '¹',
+        '2' => '²',
+        '3' => '³',
+        '4' => '⁴',
+        '5' => '⁵',
+        '6' => '⁶',
+        '7' => '⁷',
+        '8' => '⁸',
+        '9' => '⁹',
+        '+' => '⁺',
+        '-' | '−' => '⁻',
+        '=' => '⁼',
+        '(' => '⁽',
+        ')' => '⁾',
+        'i' => 'ⁱ',
+        'n' => 'ⁿ',
+        'x' => 'ˣ',
+        'T' => 'ᵀ',
+        '*' | '∗' => '*',
+        '′' | '\'' => '′',
+        _ => return None,
+    })
+}
+
+fn subscript(c: char) -> Option<char> {
+    Some(match c {
+        '0' => '₀',
+        '1' => '₁',
+        '2' => '₂',
+        '3' => '₃',
+        '4' => '₄',
+        '5' => '₅',
+        '6' => '₆',
+        '7' => '₇',
+        '8' => '₈',
+        '9' => '₉',
+        '+' => '₊',
+        '-' | '−' => '₋',
+        '=' => '₌',
+        '(' => '₍',
+        ')' => '₎',
+        'a' => 'ₐ',
+        'e' => 'ₑ',
+        'h' => 'ₕ',
+        'i' => 'ᵢ',
+        'j' => 'ⱼ',
+        'k' => 'ₖ',
+        'l' => 'ₗ',
+        'm' => 'ₘ',
+        'n' => 'ₙ',
+        'o' => 'ₒ',
+        'p' => 'ₚ',
+        'r' => 'ᵣ',
+        's' => 'ₛ',
+        't' => 'ₜ',
+        'x' => 'ₓ',
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markdown::MarkdownRenderer;
+
+    #[test]
+    fn math_keeps_its_delimiters_and_underscores() {
+        let plain = MarkdownRenderer::new().render_plain("where $a_1 * b_2$ holds").unwrap();
+        assert_eq!(plain.trim(), "where $a_1 * b_2$ holds");
+    }
+
+    #[test]
+    fn simple_math_becomes_unicode() {
+        assert_eq!(prepare("$x_1$", true), "x₁");
+        assert_eq!(prepare("$x^2 \\le \\alpha$", true), "x² ≤ α");
+    }
+
+    #[test]
+    fn prices_are_not_math() {
+        let text = "It costs $5 and $10 today\n";
+        assert_eq!(prepare(text, false), text);
+        assert_eq!(prepare(text, true), text);
+    }
+
+    #[test]
+    fn dollars_in_code_are_left_alone() {
+        let text = "Run:\n\n    echo $a$b\n\tfoo $x$\n\nand `$a$b` inline\n";
+        assert_eq!(prepare(text, true), text);
+        assert_eq!(prepare(text, false), text);
+    }
+
+    #[test]
+    fn indented_list_continuations_are_still_prose() {
+        let text = "- item\n\n    with $x_1$\n";
+        assert_eq!(prepare(text, true), "- item\n\n    with x₁\n");
+    }
+}
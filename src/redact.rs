@@ -0,0 +1,47 @@
+use anyhow::Result;
+use regex::Regex;
+
+const REDACTED: &str = "[REDACTED]";
+
+// Common credential formats; a whole match is replaced, so key names in assignments go too
+const DEFAULT_PATTERNS: &[&str] = &[
+    r"sk-ant-[A-Za-z0-9_-]{20,}",
+    r"sk-[A-Za-z0-9_-]{20,}",
+    r"AKIA[0-9A-Z]{16}",
+    r"gh[pousr]_[A-Za-z0-9]{36,}",
+    r"github_pat_[A-Za-z0-9_]{22,}",
+    r"xox[abprs]-[A-Za-z0-9-]{10,}",
+    r"-----BEGIN [A-Z ]*PRIVATE KEY-----[\s\S]*?-----END [A-Z ]*PRIVATE KEY-----",
+    r"(?i)bearer\s+[A-Za-z0-9._~+/-]{20,}=*",
+    r#"(?i)(api[_-]?key|secret|token|password|passwd)\s*[:=]\s*["']?[^\s"']{8,}["']?"#,
+];
+
+/// Replaces secrets in tool output with `[REDACTED]` before Claude sees it
+#[derive(Clone)]
+pub struct Redactor {
+    patterns: Vec<Regex>,
+}
+
+impl Redactor {
+    /// The built-in patterns when `defaults` is set, followed by any custom ones
+    pub fn new(defaults: bool, custom: &[String]) -> Result<Self> {
+        let defaults = DEFAULT_PATTERNS.iter().filter(|_| defaults).map(|pattern| pattern.to_string());
+        let patterns = defaults
+            .chain(custom.iter().cloned())
+            .map(|pattern| {
+                Regex::new(&pattern).map_err(|e| anyhow::anyhow!("Invalid redaction pattern {}: {}", pattern, e))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { patterns })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    pub fn redact(&self, text: &str) -> String {
+        self.patterns.iter().fold(text.to_string(), |text, pattern| {
+            pattern.replace_all(&text, REDACTED).into_owned()
+        })
+    }
+}
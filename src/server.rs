@@ -0,0 +1,142 @@
+use anyhow::Result;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+use crate::api::ClaudeClient;
+use crate::config::Config;
+use crate::headless::{complete, tool_handler};
+use crate::mcp::{McpHandler, ToolLog};
+
+/// Shared by every request; tools are limited to read-only ones since nobody is there to
+/// approve side effects
+struct ServerState {
+    client: ClaudeClient,
+    mcp_handler: McpHandler,
+    config: Config,
+    /// One permit per request allowed to call the API at a time
+    permits: Semaphore,
+    /// Requests waiting for a permit
+    queued: AtomicUsize,
+}
+
+#[derive(Deserialize)]
+struct ChatRequest {
+    prompt: String,
+    #[serde(default)]
+    system: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ChatResponse {
+    response: String,
+    stop_reason: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+/// Serves `POST /chat` until the process is stopped
+pub async fn serve(addr: SocketAddr, client: ClaudeClient, config: Config, tool_log: Option<ToolLog>) -> Result<()> {
+    let mcp_handler = tool_handler(&config, tool_log);
+    let permits = Semaphore::new(config.serve_max_concurrent());
+    let state = Arc::new(ServerState {
+        client,
+        mcp_handler,
+        config,
+        permits,
+        queued: AtomicUsize::new(0),
+    });
+
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |request| handle(request, state.clone()))) }
+    });
+
+    if !addr.ip().is_loopback() {
+        eprintln!("Warning: listening on {}, which is reachable from other machines", addr.ip());
+    }
+    println!("Listening on http://{} - POST /chat with {{\"prompt\": \"...\"}}", addr);
+    Server::try_bind(&addr)?.serve(make_service).await?;
+    Ok(())
+}
+
+async fn handle(request: Request<Body>, state: Arc<ServerState>) -> Result<Response<Body>, Infallible> {
+    if (request.method(), request.uri().path()) != (&Method::POST, "/chat") {
+        return Ok(json_response(StatusCode::NOT_FOUND, &ErrorResponse {
+            error: "Not found - use POST /chat".to_string(),
+        }));
+    }
+
+    let body = match hyper::body::to_bytes(request.into_body()).await {
+        Ok(body) => body,
+        Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, e.into())),
+    };
+    let chat_request: ChatRequest = match serde_json::from_slice(&body) {
+        Ok(chat_request) => chat_request,
+        Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, e.into())),
+    };
+
+    // Bursts share one API key, so only a few requests go upstream at once and the rest queue
+    let _permit = match state.permits.try_acquire() {
+        Ok(permit) => permit,
+        Err(_) => {
+            if state.queued.fetch_add(1, Ordering::SeqCst) >= state.config.serve_max_queued() {
+                state.queued.fetch_sub(1, Ordering::SeqCst);
+                return Ok(json_response(StatusCode::TOO_MANY_REQUESTS, &ErrorResponse {
+                    error: "Too many requests waiting - try again later".to_string(),
+                }));
+            }
+            let waited = tokio::time::timeout(state.config.serve_queue_timeout(), state.permits.acquire()).await;
+            state.queued.fetch_sub(1, Ordering::SeqCst);
+            match waited {
+                Ok(Ok(permit)) => permit,
+                _ => {
+                    return Ok(json_response(StatusCode::TOO_MANY_REQUESTS, &ErrorResponse {
+                        error: "Timed out waiting for a free slot - try again later".to_string(),
+                    }));
+                }
+            }
+        }
+    };
+
+    Ok(match chat(&state, chat_request).await {
+        Ok(response) => json_response(StatusCode::OK, &response),
+        Err(e) => error_response(StatusCode::BAD_GATEWAY, e),
+    })
+}
+
+async fn chat(state: &ServerState, chat_request: ChatRequest) -> Result<ChatResponse> {
+    let completion = complete(
+        &state.client,
+        &state.mcp_handler,
+        &state.config,
+        chat_request.prompt,
+        chat_request.system.as_deref(),
+    )
+    .await?;
+    Ok(ChatResponse {
+        response: completion.text,
+        stop_reason: completion.stop_reason,
+    })
+}
+
+fn error_response(status: StatusCode, error: anyhow::Error) -> Response<Body> {
+    json_response(status, &ErrorResponse { error: error.to_string() })
+}
+
+fn json_response(status: StatusCode, body: &impl Serialize) -> Response<Body> {
+    let body = serde_json::to_string(body).unwrap_or_else(|_| "{}".to_string());
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(Body::from(body))
+        .unwrap_or_else(|_| Response::new(Body::empty()))
+}
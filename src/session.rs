@@ -0,0 +1,224 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::api::{Message, Usage};
+use crate::config::Config;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    pub title: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// System prompt for this conversation only, set with `/system`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    /// Scratchpad entries added with `/note`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
+    /// Tokens used by every response in this conversation
+    #[serde(default)]
+    pub usage: Usage,
+    /// Where the chat was scrolled to (lines up from the bottom) and the selected message, so a
+    /// resumed session opens where it was left
+    #[serde(default)]
+    pub scroll_offset: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selected: Option<usize>,
+    /// The session this one was copied from with `/fork`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forked_from: Option<String>,
+    pub messages: Vec<Message>,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::new_v4().to_string(),
+            title: String::new(),
+            created_at: now,
+            updated_at: now,
+            system: None,
+            notes: Vec::new(),
+            usage: Usage::default(),
+            scroll_offset: 0,
+            selected: None,
+            forked_from: None,
+            messages: Vec::new(),
+        }
+    }
+}
+
+pub struct SessionStore {
+    dir: PathBuf,
+}
+
+impl SessionStore {
+    pub fn new(config: &Config) -> Result<Self> {
+        let dir = match &config.output_dir {
+            Some(_) => config.output_path("sessions"),
+            None => dirs::data_dir()
+                .ok_or_else(|| anyhow::anyhow!("Could not determine data directory"))?
+                .join("claude-chatbot")
+                .join("sessions"),
+        };
+        Ok(Self { dir })
+    }
+
+    pub fn save(&self, session: &Session) -> Result<PathBuf> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.path_for(&session.id);
+        fs::write(&path, serde_json::to_string_pretty(session)?)?;
+        Ok(path)
+    }
+
+    pub fn load(&self, id: &str) -> Result<Session> {
+        // Ids become file names, so anything that could point outside the sessions directory is refused
+        if id.is_empty() || id.contains(['/', '\\']) || id.contains("..") {
+            return Err(anyhow::anyhow!("Invalid session id {}", id));
+        }
+        let path = self.path_for(id);
+        let contents = fs::read_to_string(&path)
+            .map_err(|_| anyhow::anyhow!("No saved session with id {}", id))?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    /// All saved sessions, most recently updated first
+    pub fn list(&self) -> Result<Vec<Session>> {
+        let mut sessions = Vec::new();
+        if !self.dir.exists() {
+            return Ok(sessions);
+        }
+
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            if let Ok(session) = serde_json::from_str::<Session>(&fs::read_to_string(&path)?) {
+                sessions.push(session);
+            }
+        }
+
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.updated_at));
+        Ok(sessions)
+    }
+
+    fn path_for(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+}
+
+/// A JSONL transcript that every run appends to, one message per line, with a timestamped
+/// separator line at the start of each run. Independent of saved sessions
+pub struct SessionLog {
+    file: File,
+    /// The messages as last written, so edits and resends are noticed and not only growth
+    written: Vec<String>,
+}
+
+impl SessionLog {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| anyhow::anyhow!("Failed to open session log {}: {}", path.display(), e))?;
+        writeln!(file, "{}", json!({ "run_started_at": Utc::now().to_rfc3339() }))?;
+        file.flush()?;
+        Ok(Self { file, written: Vec::new() })
+    }
+
+    /// Writes every message from the first one that differs from what was last written, so an
+    /// edited or resent message is logged again. History removed by /undo or /retry stays in
+    /// the log; whatever replaces it is appended after
+    pub fn append(&mut self, messages: &[Message]) -> Result<()> {
+        let lines = messages.iter().map(serde_json::to_string).collect::<Result<Vec<_>, _>>()?;
+        let unchanged = self.written.iter().zip(&lines).take_while(|(written, line)| written == line).count();
+        for line in &lines[unchanged..] {
+            writeln!(self.file, "{}", line)?;
+        }
+        self.file.flush()?;
+        self.written = lines;
+        Ok(())
+    }
+
+    /// Treats `messages` as already written, e.g. none for a new conversation
+    pub fn mark_written(&mut self, messages: &[Message]) {
+        self.written = messages.iter().map(|message| serde_json::to_string(message).unwrap_or_default()).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_rejects_ids_outside_the_sessions_directory() {
+        let store = SessionStore { dir: PathBuf::from("sessions") };
+        for id in ["../../x", "a/b", "a\\b", "..", ""] {
+            let error = store.load(id).unwrap_err().to_string();
+            assert!(error.starts_with("Invalid session id"), "{}: {}", id, error);
+        }
+    }
+
+    fn message(role: &str, text: &str) -> Message {
+        Message {
+            role: role.to_string(),
+            content: crate::api::MessageContent::Text(text.to_string()),
+            stop_reason: None,
+            model: None,
+        }
+    }
+
+    #[test]
+    fn session_log_records_edited_and_resent_messages() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.jsonl");
+        let mut log = SessionLog::open(&path).unwrap();
+        let mut messages = vec![message("user", "Hello"), message("assistant", "Hi")];
+        log.append(&messages).unwrap();
+
+        // An edit in place keeps the length the same
+        messages[1] = message("assistant", "Hi there");
+        log.append(&messages).unwrap();
+        // A resend drops the reply and adds a new one
+        messages[1] = message("assistant", "Hello again");
+        log.append(&messages).unwrap();
+        log.append(&messages).unwrap();
+
+        let logged: Vec<String> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|line| serde_json::from_str::<Message>(line).unwrap().text())
+            .collect();
+        assert_eq!(logged, ["Hello", "Hi", "Hi there", "Hello again"]);
+    }
+
+    #[test]
+    fn session_usage_reads_cache_fields_and_older_files_without_them() {
+        let saved = r#"{
+            "id": "1", "title": "", "created_at": "2026-01-01T00:00:00Z", "updated_at": "2026-01-01T00:00:00Z",
+            "usage": { "input_tokens": 100, "output_tokens": 20, "cache_creation_input_tokens": 300, "cache_read_input_tokens": 1200 },
+            "messages": []
+        }"#;
+        let session: Session = serde_json::from_str(saved).unwrap();
+        assert_eq!(session.usage.input_tokens, 100);
+        assert_eq!(session.usage.cache_creation_input_tokens, Some(300));
+        assert_eq!(session.usage.cache_read_input_tokens, Some(1200));
+        assert!(session.usage.used_cache());
+
+        let older = saved.replace(r#", "cache_creation_input_tokens": 300, "cache_read_input_tokens": 1200"#, "");
+        let session: Session = serde_json::from_str(&older).unwrap();
+        assert_eq!(session.usage.cache_read_input_tokens, None);
+        assert!(!session.usage.used_cache());
+    }
+}
@@ -1,23 +1,77 @@
 use anyhow::Result;
+use base64::Engine;
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind},
+    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind, KeyModifiers},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use ratatui::{
     backend::CrosstermBackend,
-    layout::{Constraint, Direction, Layout},
+    layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
-    text::{Line, Span},
-    widgets::{Block, Borders, List, ListItem, Paragraph},
+    text::{Line, Span, Text},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph},
     Frame, Terminal,
 };
-use std::io;
+use serde_json::Value;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-use crate::api::{ClaudeClient, Message, MessageContent, MessageRequest, ContentBlock, ResponseContent};
-use crate::artifacts::{ArtifactManager, Artifact};
-use crate::mcp::McpHandler;
+use crate::api::{recent_messages, resolve_model_alias, supports_long_output, ClaudeClient, Message, MessageContent, MessageRequest, MessageResponse, ContentBlock, ResponseContent, ToolResultContent, STANDARD_MAX_OUTPUT_TOKENS};
+use crate::artifacts::{dump_artifacts, open_directory, source_extension, ArtifactAction, ArtifactManager, Artifact};
+use crate::attachments::load_attachment;
+use crate::color::Palette;
+use crate::commands::Command;
+use crate::config::Config;
+use crate::diff::render_diff;
+use crate::explain::explain_error;
+use crate::export;
+use crate::history::InputHistory;
+use crate::mcp::{McpHandler, ToolLog};
 use crate::markdown::MarkdownRenderer;
+use crate::session::{Session, SessionLog, SessionStore};
+use crate::wrap::{wrap_styled, Overflow};
+
+type Tui = Terminal<CrosstermBackend<io::Stdout>>;
+
+// Streamed text is redrawn at most this often to keep partial markdown from flickering
+const STREAM_REDRAW_INTERVAL: Duration = Duration::from_millis(150);
+
+const ERROR_REPLY_PREFIX: &str = "Error: ";
+
+const SUMMARY_PROMPT: &str = "Summarize our conversation so far in a few short paragraphs or bullet \
+points: the questions discussed, conclusions reached and anything left open. Reply with the summary only.";
+
+// For the status bar's size gauge; close enough for English prose and code
+const APPROX_CHARS_PER_TOKEN: usize = 4;
+
+// Columns Left/Right scroll code blocks by
+const CODE_PAN_STEP: usize = 8;
+
+// How often keys are checked for a cancel request while a response streams
+const KEY_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+enum StreamOutcome {
+    Complete(MessageResponse),
+    Cancelled { discard_prompt: bool },
+}
+
+struct Overlay {
+    title: String,
+    lines: Vec<Line<'static>>,
+    scroll: u16,
+    /// Text copied to the clipboard when `c` is pressed, for overlays that support it
+    copy_text: Option<String>,
+    /// Whether `n` saves `copy_text` to the session's notes
+    save_as_note: bool,
+    /// The artifact being previewed, for `s` to switch between rendered and source views
+    artifact: Option<usize>,
+}
 
 pub struct ChatApp {
     client: ClaudeClient,
@@ -28,22 +82,144 @@ pub struct ChatApp {
     mcp_handler: McpHandler,
     markdown_renderer: MarkdownRenderer,
     scroll_offset: usize,
+    config: Config,
+    streaming: Option<String>,
+    status_message: Option<String>,
+    pending_attachments: Vec<(String, ContentBlock)>,
+    session: Session,
+    session_store: SessionStore,
+    overlay: Option<Overlay>,
+    last_response: Option<MessageResponse>,
+    show_raw: bool,
+    render_markdown: bool,
+    compact: bool,
+    transcript: bool,
+    selected: Option<usize>,
+    // Chat pane geometry from the last draw, so scrolling can be clamped outside of `ui`
+    max_scroll: Cell<usize>,
+    /// The furthest the selected message's code can be panned, as of the last frame
+    max_code_pan: Cell<usize>,
+    /// How far the open overlay can scroll once its lines are wrapped, as of the last frame
+    max_overlay_scroll: Cell<u16>,
+    page_height: Cell<usize>,
+    // First list row of each message, for jumping straight to one
+    message_rows: RefCell<Vec<usize>>,
+    // Rendered lines per message, keyed by `render_key`; entries unused in a frame are dropped
+    render_cache: RefCell<HashMap<u64, Vec<Line<'static>>>>,
+    send_on_start: bool,
+    session_log: Option<SessionLog>,
+    /// Columns the selected message's code blocks are scrolled by, with `code_overflow = "scroll"`
+    code_pan: usize,
+    /// Extra instructions for the reply being regenerated by /retry-with; never stored in history
+    steering: Option<String>,
+    history: InputHistory,
+    palette: Palette,
+    /// Artifacts (by identifier) whose preview shows the raw source instead of the rendered form
+    artifact_source: HashSet<String>,
+    /// The most recent request or tool failure, for `/why`
+    last_error: Option<String>,
 }
 
 impl ChatApp {
-    pub fn new(client: ClaudeClient) -> Self {
+    pub fn new(client: ClaudeClient, config: Config) -> Self {
+        let status_message = (config.max_tokens() > STANDARD_MAX_OUTPUT_TOKENS && !supports_long_output(config.model()))
+            .then(|| format!(
+                "Warning: {} may not support more than {} output tokens",
+                config.model(),
+                STANDARD_MAX_OUTPUT_TOKENS
+            ));
+
         Self {
             client,
             messages: Vec::new(),
             input: String::new(),
             artifacts: Vec::new(),
-            artifact_manager: ArtifactManager::new().expect("Failed to create artifact manager"),
-            mcp_handler: McpHandler::new(),
-            markdown_renderer: MarkdownRenderer::new(),
+            artifact_manager: ArtifactManager::new()
+                .expect("Failed to create artifact manager")
+                .with_type_filter(config.artifact_types.clone(), config.ignore_artifact_types.clone())
+                .with_actions(config.artifact_actions.clone()),
+            mcp_handler: McpHandler::new(config.safe)
+                .with_precision(config.number_precision())
+                .with_live_weather(config.live_weather)
+                .with_external_tools(config.external_tools.clone())
+                .with_redactor(config.redactor())
+                .with_exposed_env(config.system_info_env.clone())
+                .with_max_result_chars(config.max_tool_result_chars()),
+            markdown_renderer: MarkdownRenderer::new()
+                .with_code_labels(config.code_labels)
+                .with_line_numbers(config.line_numbers)
+                .with_unicode_math(config.unicode_math)
+                .with_color_level(config.color_level())
+                .with_code_overflow(config.code_overflow)
+                .with_gutter_color(config.background().palette().muted)
+                .with_inline_code_color(config.background().palette().inline_code)
+                .with_theme(config.syntax_theme()),
             scroll_offset: 0,
+            streaming: None,
+            status_message,
+            pending_attachments: Vec::new(),
+            session: Session::new(),
+            session_store: SessionStore::new(&config).expect("Failed to locate session directory"),
+            overlay: None,
+            last_response: None,
+            show_raw: false,
+            render_markdown: true,
+            compact: config.compact,
+            transcript: false,
+            selected: None,
+            max_scroll: Cell::new(0),
+            max_code_pan: Cell::new(0),
+            max_overlay_scroll: Cell::new(0),
+            page_height: Cell::new(0),
+            message_rows: RefCell::new(Vec::new()),
+            render_cache: RefCell::new(HashMap::new()),
+            send_on_start: false,
+            session_log: None,
+            code_pan: 0,
+            steering: None,
+            history: InputHistory::load(config.history_size()),
+            palette: config.background().palette(),
+            artifact_source: HashSet::new(),
+            last_error: None,
+            config,
         }
     }
 
+    pub fn set_tool_log(&mut self, tool_log: ToolLog) {
+        self.mcp_handler.set_tool_log(tool_log);
+    }
+
+    /// Replaces the conversation's system prompt, noting which files it was assembled from
+    pub fn set_system_prompt(&mut self, prompt: String, files: &[PathBuf]) {
+        if !files.is_empty() {
+            let names: Vec<String> = files.iter().map(|path| path.display().to_string()).collect();
+            self.status_message = Some(format!("Loaded {} into the system prompt", names.join(", ")));
+        }
+        self.session.system = Some(prompt);
+    }
+
+    /// Appends the conversation to a transcript as it grows. A resumed history is left out
+    /// since it was already logged by the run that produced it
+    pub fn set_session_log(&mut self, mut session_log: SessionLog) {
+        session_log.mark_written(&self.messages);
+        self.session_log = Some(session_log);
+    }
+
+    /// Pre-fills the input box, optionally sending it as soon as the UI starts
+    pub fn set_initial_prompt(&mut self, prompt: String, send: bool) {
+        self.input = prompt;
+        self.send_on_start = send;
+    }
+
+    /// Continues a saved session, back at its scroll position. The offset is clamped when the
+    /// chat is drawn, since the pane may be a different size now
+    pub fn resume(&mut self, session: Session) {
+        self.messages = session.messages.clone();
+        self.scroll_offset = session.scroll_offset;
+        self.selected = session.selected.filter(|&selected| selected < self.messages.len());
+        self.session = session;
+    }
+
     pub async fn run(&mut self) -> Result<()> {
         enable_raw_mode()?;
         let mut stdout = io::stdout();
@@ -51,230 +227,1719 @@ impl ChatApp {
         let backend = CrosstermBackend::new(stdout);
         let mut terminal = Terminal::new(backend)?;
 
+        // Raw mode turns Ctrl+C into a key event, but a SIGINT sent from elsewhere would
+        // otherwise kill us with the terminal still in raw mode
+        tokio::spawn(async {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                let _ = restore_terminal();
+                std::process::exit(130);
+            }
+        });
+
+        if self.send_on_start && !self.input.trim().is_empty() {
+            self.submit_input(&mut terminal).await;
+        }
+
         loop {
+            self.append_to_log();
             terminal.draw(|f| self.ui(f))?;
 
             if let Event::Key(key) = event::read()? {
                 if key.kind == KeyEventKind::Press {
-                    match key.code {
-                        KeyCode::Char('q') if key.modifiers.contains(crossterm::event::KeyModifiers::CONTROL) => {
-                            break;
-                        }
-                        KeyCode::Enter => {
-                            if !self.input.trim().is_empty() {
-                                let user_input = self.input.clone();
-                                self.input.clear();
-                                
-                                // Add user message
-                                self.messages.push(Message {
-                                    role: "user".to_string(),
-                                    content: MessageContent::Text(user_input.clone()),
-                                });
-
-                                // Send to Claude
-                                if let Err(e) = self.send_message().await {
-                                    self.messages.push(Message {
-                                        role: "assistant".to_string(),
-                                        content: MessageContent::Text(format!("Error: {}", e)),
+                    if let Some(overlay) = self.overlay.as_mut() {
+                        match key.code {
+                            KeyCode::Esc | KeyCode::Enter => self.overlay = None,
+                            KeyCode::Up => overlay.scroll = overlay.scroll.saturating_sub(1),
+                            KeyCode::Down => {
+                                overlay.scroll = overlay.scroll.saturating_add(1).min(self.max_overlay_scroll.get());
+                            }
+                            KeyCode::Char('c') => {
+                                if let Some(text) = &overlay.copy_text {
+                                    self.status_message = Some(match copy_to_clipboard(text) {
+                                        Ok(()) => "Copied to the clipboard".to_string(),
+                                        Err(e) => format!("Failed to copy: {}", e),
                                     });
                                 }
                             }
+                            KeyCode::Char('s') if overlay.artifact.is_some() => {
+                                if let Some(index) = overlay.artifact {
+                                    let id = &self.artifacts[index].id;
+                                    if !self.artifact_source.remove(id) {
+                                        self.artifact_source.insert(id.clone());
+                                    }
+                                    let scroll = overlay.scroll;
+                                    self.overlay = Some(Overlay { scroll, ..self.artifact_overlay(index) });
+                                }
+                            }
+                            KeyCode::Char('n') if overlay.save_as_note => {
+                                if let Some(text) = overlay.copy_text.take() {
+                                    self.session.notes.push(text);
+                                    self.status_message = Some(format!("Saved as note {}", self.session.notes.len()));
+                                }
+                                self.overlay = None;
+                            }
+                            _ => {}
                         }
+                        continue;
+                    }
+
+                    if self.transcript {
+                        match key.code {
+                            KeyCode::Char('q') | KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                                break;
+                            }
+                            KeyCode::Char('t') if key.modifiers.contains(KeyModifiers::CONTROL) => self.transcript = false,
+                            KeyCode::Esc => self.transcript = false,
+                            KeyCode::Up => self.scroll_by(1),
+                            KeyCode::Down => self.scroll_by(-1),
+                            KeyCode::PageUp => self.scroll_by(self.page_height.get() as isize),
+                            KeyCode::PageDown => self.scroll_by(-(self.page_height.get() as isize)),
+                            KeyCode::Home => self.scroll_offset = self.max_scroll.get(),
+                            KeyCode::End => self.scroll_offset = 0,
+                            KeyCode::Char('[') => self.jump_to_user_message(-1),
+                            KeyCode::Char(']') => self.jump_to_user_message(1),
+                            _ => {}
+                        }
+                        continue;
+                    }
+
+                    match key.code {
+                        KeyCode::Char('q') | KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                            break;
+                        }
+                        KeyCode::Char('t') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                            self.transcript = true;
+                        }
+                        KeyCode::Char('n') if key.modifiers.contains(KeyModifiers::CONTROL) => self.new_session(),
+                        KeyCode::Char('o') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                            self.render_markdown = !self.render_markdown;
+                            self.status_message = Some(if self.render_markdown {
+                                "Rendering markdown".to_string()
+                            } else {
+                                "Showing raw text for all messages - Ctrl+O to render markdown again".to_string()
+                            });
+                        }
+                        KeyCode::Char('d') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                            self.compact = !self.compact;
+                        }
+                        KeyCode::Char('p') if key.modifiers.contains(KeyModifiers::CONTROL) => self.show_system_prompt(),
+                        KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                            self.status_message = None;
+                            self.resend(&mut terminal).await;
+                        }
+                        KeyCode::Enter if !self.input.trim().is_empty() => {
+                            self.submit_input(&mut terminal).await;
+                        }
+                        KeyCode::Up if key.modifiers.contains(KeyModifiers::CONTROL) => self.jump_to_user_message(-1),
+                        KeyCode::Down if key.modifiers.contains(KeyModifiers::CONTROL) => self.jump_to_user_message(1),
                         KeyCode::Char(c) => {
                             self.input.push(c);
                         }
                         KeyCode::Backspace => {
                             self.input.pop();
                         }
-                        KeyCode::Up => {
-                            if self.scroll_offset > 0 {
-                                self.scroll_offset -= 1;
+                        KeyCode::Up if key.modifiers.contains(KeyModifiers::SHIFT) => {
+                            if let Some(entry) = self.history.previous(&self.input) {
+                                self.input = entry;
                             }
                         }
-                        KeyCode::Down => {
-                            self.scroll_offset += 1;
-                        }
-                        KeyCode::Tab => {
-                            if !self.artifacts.is_empty() {
-                                let latest_artifact = &self.artifacts[self.artifacts.len() - 1];
-                                let _ = self.artifact_manager.display_artifact(latest_artifact);
+                        KeyCode::Down if key.modifiers.contains(KeyModifiers::SHIFT) => {
+                            if let Some(entry) = self.history.next() {
+                                self.input = entry;
                             }
                         }
+                        KeyCode::Up if key.modifiers.contains(KeyModifiers::ALT) => self.move_selection(-1),
+                        KeyCode::Down if key.modifiers.contains(KeyModifiers::ALT) => self.move_selection(1),
+                        KeyCode::Esc if self.selected.is_some() => {
+                            self.selected = None;
+                        }
+                        KeyCode::Left if self.selected.is_some() && self.config.code_overflow == Overflow::Scroll => {
+                            self.code_pan = self.code_pan.saturating_sub(CODE_PAN_STEP);
+                        }
+                        KeyCode::Right if self.selected.is_some() && self.config.code_overflow == Overflow::Scroll => {
+                            self.code_pan = (self.code_pan + CODE_PAN_STEP).min(self.max_code_pan.get());
+                        }
+                        // Scrolling counts lines up from the bottom, so 0 follows the latest message
+                        KeyCode::Up => self.scroll_by(1),
+                        KeyCode::Down => self.scroll_by(-1),
+                        KeyCode::PageUp => self.scroll_by(self.page_height.get() as isize),
+                        KeyCode::PageDown => self.scroll_by(-(self.page_height.get() as isize)),
+                        KeyCode::Tab if !self.artifacts.is_empty() => {
+                            let index = self.artifacts.len() - 1;
+                            let action = self.artifact_manager.action_for(&self.artifacts[index].content_type);
+                            self.handle_artifact(index, action);
+                        }
+                        KeyCode::BackTab => self.open_artifact_dir(),
                         _ => {}
                     }
                 }
             }
         }
 
-        disable_raw_mode()?;
-        execute!(
-            terminal.backend_mut(),
-            LeaveAlternateScreen,
-            DisableMouseCapture
-        )?;
+        self.append_to_log();
+        restore_terminal()?;
         terminal.show_cursor()?;
 
         Ok(())
     }
 
-    async fn send_message(&mut self) -> Result<()> {
-        let tools = ClaudeClient::get_tools();
+    async fn submit_input(&mut self, terminal: &mut Tui) {
+        let user_input = self.input.clone();
+        self.input.clear();
+        self.status_message = None;
+        if let Err(e) = self.history.push(&user_input) {
+            self.status_message = Some(format!("Failed to save input history: {}", e));
+        }
+
+        if let Some(command) = Command::parse(&user_input) {
+            self.handle_command(command, terminal).await;
+            return;
+        }
         
-        let request = MessageRequest {
-            model: "claude-sonnet-4-20250514".to_string(),
-            max_tokens: 4000,
-            messages: self.messages.clone(),
-            tools: Some(tools),
+        // Add user message, along with anything queued with /attach
+        let content = if self.pending_attachments.is_empty() {
+            MessageContent::Text(user_input.clone())
+        } else {
+            let mut blocks: Vec<ContentBlock> = self.pending_attachments
+                .drain(..)
+                .map(|(_, block)| block)
+                .collect();
+            blocks.push(ContentBlock::Text { text: user_input.clone() });
+            MessageContent::Blocks(blocks)
         };
+        self.messages.push(Message {
+            role: "user".to_string(),
+            content,
+            stop_reason: None,
+            model: None,
+        });
 
-        let response = self.client.send_message(request).await?;
-        
-        let mut response_blocks = Vec::new();
-        let mut full_text = String::new();
-
-        for content in response.content {
-            match content {
-                ResponseContent::Text { text } => {
-                    full_text.push_str(&text);
-                    response_blocks.push(ContentBlock::Text { text });
+        self.request_reply(terminal, None).await;
+    }
+
+    /// Sends the conversation to Claude, recording any failure as an error reply
+    async fn request_reply(&mut self, terminal: &mut Tui, model: Option<String>) {
+        if let Err(e) = self.send_message(terminal, model).await {
+            self.messages.push(Message {
+                role: "assistant".to_string(),
+                content: MessageContent::Text(format!("{}{}", ERROR_REPLY_PREFIX, e)),
+                stop_reason: None,
+                model: None,
+            });
+            self.last_error = Some(e.to_string());
+            self.status_message = Some("Request failed - /resend or Ctrl+R to try again, /why for help".to_string());
+        }
+    }
+
+    /// Drops a failed request's error reply and sends the conversation again as it was
+    async fn resend(&mut self, terminal: &mut Tui) {
+        if !self.messages.last().is_some_and(is_error_reply) {
+            self.status_message = Some("The last reply isn't an error - use /retry to regenerate it".to_string());
+            return;
+        }
+        self.messages.pop();
+        self.selected = self.selected.filter(|&selected| selected < self.messages.len());
+        self.request_reply(terminal, None).await;
+    }
+
+    fn scroll_by(&mut self, lines: isize) {
+        self.scroll_offset = self.scroll_offset
+            .saturating_add_signed(lines)
+            .min(self.max_scroll.get());
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        if self.messages.is_empty() {
+            return;
+        }
+        self.code_pan = 0;
+        let last = self.messages.len() - 1;
+        self.selected = match self.selected {
+            None if delta < 0 => Some(last),
+            None => None,
+            Some(index) if delta > 0 && index >= last => None,
+            Some(index) => Some(index.saturating_add_signed(delta).min(last)),
+        };
+    }
+
+    /// Saves the latest artifact and opens its directory in the file manager, or shows the path
+    /// when that isn't possible
+    fn open_artifact_dir(&mut self) {
+        let Some(artifact) = self.artifacts.last() else {
+            self.status_message = Some("No artifacts yet".to_string());
+            return;
+        };
+        self.status_message = Some(match self.artifact_manager.save_artifact(artifact) {
+            Ok(path) => match open_directory(self.artifact_manager.dir()) {
+                Ok(()) => format!("Opened {}", self.artifact_manager.dir().display()),
+                Err(e) => format!("Saved to {} ({} to open the folder)", path.display(), e),
+            },
+            Err(e) => format!("Failed to save artifact: {}", e),
+        });
+    }
+
+    /// Selects the previous or next message you typed and scrolls it to the top of the pane.
+    /// Moving past the last one returns to following the latest message
+    fn jump_to_user_message(&mut self, direction: isize) {
+        let current = self.selected.unwrap_or(self.messages.len());
+        let target = if direction < 0 {
+            self.messages[..current.min(self.messages.len())].iter().rposition(Message::is_user_turn)
+        } else {
+            self.messages
+                .iter()
+                .enumerate()
+                .skip(current + 1)
+                .find(|(_, message)| message.is_user_turn())
+                .map(|(index, _)| index)
+        };
+
+        match target {
+            Some(index) => {
+                self.selected = Some(index);
+                self.code_pan = 0;
+                // Offsets count up from the bottom, so the row's distance from the top is inverted
+                if let Some(&row) = self.message_rows.borrow().get(index) {
+                    let max_scroll = self.max_scroll.get();
+                    self.scroll_offset = max_scroll - row.min(max_scroll);
                 }
-                ResponseContent::ToolUse { id, name, input } => {
-                    // Handle tool call
-                    let tool_result = self.mcp_handler.handle_tool_call(&name, &input).await?;
-                    
-                    response_blocks.push(ContentBlock::ToolUse {
-                        id: id.clone(),
-                        name,
-                        input,
-                    });
-                    
-                    response_blocks.push(ContentBlock::ToolResult {
-                        tool_use_id: id,
-                        content: tool_result,
-                    });
+            }
+            None if direction > 0 => {
+                self.selected = None;
+                self.scroll_offset = 0;
+            }
+            None => {}
+        }
+    }
+
+    async fn handle_command(&mut self, command: Command, terminal: &mut Tui) {
+        match command {
+            Command::Undo => self.undo(),
+            Command::Resend => self.resend(terminal).await,
+            Command::Retry(model) => self.retry(model.as_deref().map(resolve_model_alias), terminal).await,
+            Command::RetryWith(instructions) if instructions.is_empty() => {
+                self.status_message = Some("Usage: /retry-with <instructions>".to_string());
+            }
+            Command::RetryWith(instructions) => {
+                self.steering = Some(instructions);
+                self.retry(None, terminal).await;
+                self.steering = None;
+            }
+            Command::Attach(path) => self.attach(&path),
+            Command::Detach => {
+                let count = self.pending_attachments.len();
+                self.pending_attachments.clear();
+                self.status_message = Some(format!("Cleared {} pending attachment(s)", count));
+            }
+            Command::Save(title) => self.save_session(&title),
+            Command::Fork(title) => self.fork_session(&title),
+            Command::Sessions => self.show_sessions(),
+            Command::Export { format, name } => self.export(&format, &name),
+            Command::CopyAll(format) => self.copy_all(&format),
+            Command::Dump(dir) => self.dump_artifacts(&dir),
+            Command::Preview(number) => self.preview_artifact(&number),
+            Command::Raw => {
+                self.show_raw = !self.show_raw;
+                self.status_message = Some(if self.show_raw {
+                    "Showing raw text of the selected message or latest reply - /raw again to render it".to_string()
+                } else {
+                    "Rendering markdown again".to_string()
+                });
+            }
+            Command::System(prompt) => {
+                self.status_message = Some(if prompt.is_empty() {
+                    "Cleared the system prompt for this conversation".to_string()
+                } else {
+                    "Set the system prompt for this conversation".to_string()
+                });
+                self.session.system = (!prompt.is_empty()).then_some(prompt);
+            }
+            Command::Summarize => self.summarize(terminal).await,
+            Command::Why => self.explain_last_error(),
+            Command::Think(budget) => self.set_thinking_budget(&budget),
+            Command::Note(text) if text.is_empty() => self.show_notes(),
+            Command::Note(text) => {
+                self.session.notes.push(text);
+                self.status_message = Some(format!(
+                    "Added note {} - /note on its own shows them all",
+                    self.session.notes.len()
+                ));
+            }
+            Command::Debug(what) if what == "last" => self.show_last_response(),
+            Command::Debug(_) => {
+                self.status_message = Some("Usage: /debug last".to_string());
+            }
+            Command::Unknown(name) => {
+                self.status_message = Some(format!("Unknown command: /{}", name));
+            }
+        }
+    }
+
+    fn undo(&mut self) {
+        // Drop the last user turn along with everything after it (response, tool blocks)
+        match self.messages.iter().rposition(|message| message.is_user_turn()) {
+            Some(index) => {
+                let removed = self.messages.split_off(index);
+                self.selected = self.selected.filter(|&selected| selected < index);
+                self.input = removed[0].text();
+                // Images and documents sent with the turn are queued again to go with the input
+                if let MessageContent::Blocks(blocks) = &removed[0].content {
+                    for block in blocks {
+                        if let ContentBlock::Image { source } | ContentBlock::Document { source } = block {
+                            self.pending_attachments.push((source.media_type.clone(), block.clone()));
+                        }
+                    }
                 }
+                self.status_message = Some("Removed the last turn".to_string());
+            }
+            None => {
+                self.status_message = Some("Nothing to undo".to_string());
+            }
+        }
+    }
+
+    /// Regenerates the reply to the last user turn, optionally with a different model
+    async fn retry(&mut self, model: Option<String>, terminal: &mut Tui) {
+        // Everything after the last user turn (reply, tool blocks, errors) is regenerated
+        let Some(index) = self.messages.iter().rposition(|message| message.is_user_turn()) else {
+            self.status_message = Some("Nothing to retry".to_string());
+            return;
+        };
+        self.messages.truncate(index + 1);
+        self.selected = self.selected.filter(|&selected| selected <= index);
+
+        self.request_reply(terminal, model).await;
+    }
+
+    fn save_session(&mut self, title: &str) {
+        self.status_message = Some(match self.store_session(title) {
+            Ok(()) => format!("Saved session {}", self.session.id),
+            Err(e) => format!("Failed to save session: {}", e),
+        });
+    }
+
+    /// Archives the current conversation and starts an empty one with the same settings
+    fn new_session(&mut self) {
+        if self.messages.is_empty() {
+            self.status_message = Some("Already in a new conversation - nothing to archive".to_string());
+            return;
+        }
+        if let Err(e) = self.store_session("") {
+            self.status_message = Some(format!("Failed to archive session: {}", e));
+            return;
+        }
+
+        self.append_to_log();
+        if let Some(session_log) = self.session_log.as_mut() {
+            session_log.mark_written(&[]);
+        }
+
+        let archived = std::mem::replace(&mut self.session, Session::new());
+        self.session.system = archived.system;
+        self.messages.clear();
+        self.artifacts.clear();
+        // Nothing from the old conversation should reach /copy, /raw, /why or the next message
+        self.last_response = None;
+        self.pending_attachments.clear();
+        self.last_error = None;
+        self.selected = None;
+        self.scroll_offset = 0;
+        self.status_message = Some(format!("Archived session {} - started a new conversation", archived.id));
+    }
+
+    /// Saves the conversation, then carries on in a copy of it under a new id, with the same
+    /// messages, artifacts, notes and system prompt, so the two can go their own ways
+    fn fork_session(&mut self, title: &str) {
+        if let Err(e) = self.store_session("") {
+            self.status_message = Some(format!("Failed to save session before forking: {}", e));
+            return;
+        }
+
+        let original = self.session.clone();
+        let fork = Session::new();
+        self.session.id = fork.id;
+        self.session.created_at = fork.created_at;
+        self.session.updated_at = fork.updated_at;
+        self.session.forked_from = Some(original.id.clone());
+        self.session.title = if title.is_empty() {
+            format!("{} (fork)", original.title)
+        } else {
+            title.to_string()
+        };
+        self.status_message = Some(match self.session_store.save(&self.session) {
+            Ok(_) => format!(
+                "Forked into session {} - the original is saved as {} (--resume-id to go back)",
+                self.session.id, original.id
+            ),
+            Err(e) => format!("Forked, but failed to save the new session: {}", e),
+        });
+    }
+
+    fn append_to_log(&mut self) {
+        if let Some(session_log) = self.session_log.as_mut() {
+            if let Err(e) = session_log.append(&self.messages) {
+                self.status_message = Some(format!("Failed to append to the session log: {}", e));
             }
         }
+    }
 
-        // Extract artifacts from the full text
-        let new_artifacts = self.artifact_manager.extract_artifacts(&full_text);
-        self.artifacts.extend(new_artifacts);
+    fn store_session(&mut self, title: &str) -> Result<()> {
+        if !title.is_empty() {
+            self.session.title = title.to_string();
+        } else if self.session.title.is_empty() {
+            if let Some(first) = self.messages.iter().find(|message| message.is_user_turn()) {
+                self.session.title = first.text().chars().take(50).collect();
+            }
+        }
+        self.session.messages = self.messages.clone();
+        self.session.scroll_offset = self.scroll_offset.min(self.max_scroll.get());
+        self.session.selected = self.selected;
+        self.session.updated_at = chrono::Utc::now();
+        self.session_store.save(&self.session)?;
+        Ok(())
+    }
 
-        // Add assistant response
-        if response_blocks.len() == 1 {
-            if let ContentBlock::Text { text } = &response_blocks[0] {
-                self.messages.push(Message {
-                    role: "assistant".to_string(),
-                    content: MessageContent::Text(text.clone()),
+    fn show_sessions(&mut self) {
+        match self.session_store.list() {
+            Ok(sessions) => {
+                let mut lines: Vec<Line<'static>> = sessions
+                    .iter()
+                    .map(|session| {
+                        Line::from(format!(
+                            "{}  {}  {}  ({} output tokens; input {})",
+                            session.id,
+                            session.updated_at.format("%Y-%m-%d %H:%M"),
+                            session.title,
+                            session.usage.output_tokens,
+                            session.usage.input_breakdown()
+                        ))
+                    })
+                    .collect();
+                if lines.is_empty() {
+                    lines.push(Line::from("No saved sessions yet - use /save"));
+                }
+                self.overlay = Some(Overlay {
+                    title: "Saved sessions (resume with --resume-id <id>) - Esc to close".to_string(),
+                    lines,
+                    scroll: 0,
+                    copy_text: None,
+                    save_as_note: false,
+                    artifact: None,
                 });
-            } else {
-                self.messages.push(Message {
-                    role: "assistant".to_string(),
-                    content: MessageContent::Blocks(response_blocks),
+            }
+            Err(e) => {
+                self.status_message = Some(format!("Failed to list sessions: {}", e));
+            }
+        }
+    }
+
+    /// Asks Claude for a summary of the conversation and shows it in an overlay, leaving the
+    /// history itself untouched
+    async fn summarize(&mut self, terminal: &mut Tui) {
+        if self.messages.is_empty() {
+            self.status_message = Some("Nothing to summarize yet".to_string());
+            return;
+        }
+
+        let mut messages = self.messages.clone();
+        messages.push(Message {
+            role: "user".to_string(),
+            content: MessageContent::Text(SUMMARY_PROMPT.to_string()),
+            stop_reason: None,
+            model: None,
+        });
+        let mut builder = MessageRequest::builder()
+            .model(self.config.model())
+            .max_tokens(self.config.max_tokens())
+            .messages(messages);
+        // Tools are still declared since the history may contain tool calls, which the API
+        // only accepts alongside tool definitions
+        if !self.config.no_tools {
+            builder = builder.tools(self.mcp_handler.tools());
+        }
+        if let Some(system) = &self.session.system {
+            builder = builder.system(system.clone());
+        }
+
+        self.status_message = Some("Summarizing the conversation - Esc to cancel".to_string());
+        let summary = match self.stream_response(builder.build(), terminal).await {
+            Ok(StreamOutcome::Complete(response)) => response
+                .content
+                .into_iter()
+                .filter_map(|content| match content {
+                    ResponseContent::Text { text } => Some(text),
+                    _ => None,
+                })
+                .collect::<String>(),
+            Ok(StreamOutcome::Cancelled { .. }) => {
+                self.status_message = Some("Summary cancelled".to_string());
+                return;
+            }
+            Err(e) => {
+                self.status_message = Some(format!("Failed to summarize: {}", e));
+                return;
+            }
+        };
+
+        // The overlay wraps lines itself, so code isn't hard-wrapped here
+        let lines = self
+            .markdown_renderer
+            .render_to_text(&summary, usize::MAX, 0)
+            .unwrap_or_else(|_| plain_text(&summary))
+            .lines;
+        self.status_message = None;
+        self.overlay = Some(Overlay {
+            title: "Summary - c to copy, n to save as a note, Esc to close".to_string(),
+            lines,
+            scroll: 0,
+            copy_text: Some(summary),
+            save_as_note: true,
+            artifact: None,
+        });
+    }
+
+    /// "12 messages, ~3.4k tokens": how big the conversation sent with each request has grown
+    fn conversation_size(&self) -> String {
+        let chars: usize = self.messages.iter().map(Message::char_count).sum();
+        format!(
+            "{} message{}, ~{} tokens",
+            self.messages.len(),
+            if self.messages.len() == 1 { "" } else { "s" },
+            approximate_tokens(chars)
+        )
+    }
+
+    /// `/think <tokens>` sets the extended thinking budget for the following requests, `/think off`
+    /// turns thinking off and `/think` shows the current setting
+    fn set_thinking_budget(&mut self, budget: &str) {
+        self.status_message = Some(match budget {
+            "" => match self.config.thinking_budget {
+                Some(budget) => format!("Thinking budget: {} tokens - /think off to disable", budget),
+                None => "Thinking is off - /think <tokens> to enable it".to_string(),
+            },
+            "off" => {
+                self.config.thinking_budget = None;
+                "Thinking is off".to_string()
+            }
+            _ => match budget.parse::<u32>() {
+                Ok(budget) => match self.config.check_thinking_budget(budget) {
+                    Ok(()) => {
+                        self.config.thinking_budget = Some(budget);
+                        format!("Thinking budget set to {} tokens", budget)
+                    }
+                    Err(e) => e.to_string(),
+                },
+                Err(_) => "Usage: /think <tokens|off>".to_string(),
+            },
+        });
+    }
+
+    /// Explains the last error in plain language from a built-in table of known failures
+    fn explain_last_error(&mut self) {
+        let Some(error) = &self.last_error else {
+            self.status_message = Some("No errors so far".to_string());
+            return;
+        };
+        let mut lines = vec![
+            Line::from(Span::styled("Error", Style::default().add_modifier(Modifier::BOLD))),
+            Line::from(Span::styled(error.clone(), Style::default().fg(Color::Red))),
+            Line::default(),
+        ];
+        match explain_error(error) {
+            Some((cause, fix)) => {
+                lines.push(Line::from(vec![
+                    Span::styled("What happened: ", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(cause),
+                ]));
+                lines.push(Line::from(vec![
+                    Span::styled("What to do: ", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(fix),
+                ]));
+            }
+            None => lines.push(Line::from("This isn't a known error - the message above is all there is to go on.")),
+        }
+        self.overlay = Some(Overlay {
+            title: "Why did that fail? - c to copy the error, Esc to close".to_string(),
+            lines,
+            scroll: 0,
+            copy_text: Some(error.clone()),
+            save_as_note: false,
+            artifact: None,
+        });
+    }
+
+    fn show_notes(&mut self) {
+        let lines = if self.session.notes.is_empty() {
+            vec![Line::from("No notes yet - add one with /note <text>")]
+        } else {
+            self.session
+                .notes
+                .iter()
+                .enumerate()
+                .map(|(index, note)| Line::from(format!("{}. {}", index + 1, note)))
+                .collect()
+        };
+        self.overlay = Some(Overlay {
+            title: "Notes - c to copy, Esc to close".to_string(),
+            lines,
+            scroll: 0,
+            copy_text: Some(self.session.notes.join("\n")),
+            save_as_note: false,
+            artifact: None,
+        });
+    }
+
+    fn show_system_prompt(&mut self) {
+        let Some(system) = &self.session.system else {
+            self.status_message = Some("No system prompt is set - add one with /system <prompt>".to_string());
+            return;
+        };
+        self.overlay = Some(Overlay {
+            title: "System prompt - c to copy, Esc to close".to_string(),
+            lines: system.lines().map(|line| Line::from(line.to_string())).collect(),
+            scroll: 0,
+            copy_text: Some(system.clone()),
+            save_as_note: false,
+            artifact: None,
+        });
+    }
+
+    /// Shows the last API response exactly as received, as pretty JSON
+    fn show_last_response(&mut self) {
+        let Some(response) = &self.last_response else {
+            self.status_message = Some("No response received yet".to_string());
+            return;
+        };
+        match serde_json::to_string_pretty(response) {
+            Ok(json) => {
+                self.overlay = Some(Overlay {
+                    title: "Last response - c to copy, Esc to close".to_string(),
+                    lines: json.lines().map(|line| Line::from(line.to_string())).collect(),
+                    scroll: 0,
+                    copy_text: Some(json),
+                    save_as_note: false,
+                    artifact: None,
                 });
             }
+            Err(e) => {
+                self.status_message = Some(format!("Failed to serialize the last response: {}", e));
+            }
+        }
+    }
+
+    /// Copies the whole conversation, as plain text or with `md` as Markdown
+    fn copy_all(&mut self, format: &str) {
+        let markdown = export::to_markdown(&self.session.title, &self.messages, &self.session.notes);
+        let text = match format {
+            "" | "txt" => self.markdown_renderer.render_plain(&markdown),
+            "md" | "markdown" => Ok(markdown),
+            _ => {
+                self.status_message = Some("Usage: /copy-all [txt|md]".to_string());
+                return;
+            }
+        };
+        self.status_message = Some(match text.and_then(|text| copy_to_clipboard(&text).map(|()| text.len())) {
+            Ok(bytes) => format!("Copied the conversation to the clipboard ({} bytes)", bytes),
+            Err(e) => format!("Failed to copy: {}", e),
+        });
+    }
+
+    fn export(&mut self, format: &str, name: &str) {
+        let contents = match format {
+            "md" | "markdown" => Ok(export::to_markdown(&self.session.title, &self.messages, &self.session.notes)),
+            "json" => export::to_json(&self.session.title, &self.messages, &self.session.notes),
+            // Markdown rendered with terminal colors, for viewing with `cat` or `less -R`
+            "ansi" => self.markdown_renderer.render(&export::to_markdown(&self.session.title, &self.messages, &self.session.notes)),
+            // The same rendering without any escape codes
+            "txt" => self.markdown_renderer.render_plain(&export::to_markdown(&self.session.title, &self.messages, &self.session.notes)),
+            _ => {
+                self.status_message = Some("Usage: /export <md|json|ansi|txt> [name]".to_string());
+                return;
+            }
+        };
+        let extension = match format {
+            "json" => "json",
+            "ansi" | "txt" => "txt",
+            _ => "md",
+        };
+
+        let name = if name.is_empty() {
+            format!("conversation-{}", chrono::Local::now().format("%Y%m%d-%H%M%S"))
+        } else {
+            name.to_string()
+        };
+        let mut path = self.config.output_path(&name);
+        if path.extension().is_none() {
+            path.set_extension(extension);
+        }
+
+        let result = contents.and_then(|contents| {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(&path, contents)?;
+            Ok(())
+        });
+        self.status_message = Some(match result {
+            Ok(()) => format!("Exported conversation to {}", path.display()),
+            Err(e) => format!("Failed to export: {}", e),
+        });
+    }
+
+    /// Saves every artifact so far. Without a directory, each dump gets a fresh timestamped one
+    /// under the output directory so repeated dumps never overwrite each other
+    fn dump_artifacts(&mut self, dir: &str) {
+        if self.artifacts.is_empty() {
+            self.status_message = Some("No artifacts yet".to_string());
+            return;
+        }
+
+        let dir = if dir.is_empty() {
+            let name = format!("claude-artifacts-{}", chrono::Local::now().format("%Y-%m-%dT%H-%M-%S"));
+            let mut dir = self.config.output_path(&name);
+            let mut suffix = 1;
+            while dir.exists() {
+                suffix += 1;
+                dir = self.config.output_path(&format!("{}-{}", name, suffix));
+            }
+            dir
+        } else {
+            self.config.output_path(dir)
+        };
+        self.status_message = Some(match dump_artifacts(&self.artifacts, &dir) {
+            Ok(()) => format!("Saved {} artifact(s) to {}", self.artifacts.len(), dir.display()),
+            Err(e) => format!("Failed to save artifacts: {}", e),
+        });
+    }
+
+    /// Does what `action` says with an artifact: the preview overlay for `Inline`, otherwise
+    /// whatever the artifact manager did, reported in the status bar
+    fn handle_artifact(&mut self, index: usize, action: ArtifactAction) {
+        if action == ArtifactAction::Inline {
+            self.overlay = Some(self.artifact_overlay(index));
+            return;
+        }
+        self.status_message = Some(match self.artifact_manager.display_artifact(&self.artifacts[index], action) {
+            Ok(message) => message,
+            Err(e) => format!("Failed to open artifact: {}", e),
+        });
+    }
+
+    /// `/preview [n]` shows the nth artifact (the latest by default) in a popup
+    fn preview_artifact(&mut self, number: &str) {
+        if self.artifacts.is_empty() {
+            self.status_message = Some("No artifacts yet".to_string());
+            return;
+        }
+        let index = if number.is_empty() {
+            Some(self.artifacts.len() - 1)
         } else {
+            number.parse::<usize>().ok().filter(|n| (1..=self.artifacts.len()).contains(n)).map(|n| n - 1)
+        };
+        match index {
+            Some(index) => self.overlay = Some(self.artifact_overlay(index)),
+            None => {
+                self.status_message = Some(format!("Usage: /preview [1-{}]", self.artifacts.len()));
+            }
+        }
+    }
+
+    /// An artifact's preview: highlighted code, or for HTML and React a pointer to the browser,
+    /// unless its source view was switched on with `s`
+    fn artifact_overlay(&self, index: usize) -> Overlay {
+        let artifact = &self.artifacts[index];
+        let show_source = self.artifact_source.contains(&artifact.id);
+        let lines = if show_source {
+            plain_text(&artifact.content).lines
+        } else {
+            match artifact.content_type.as_str() {
+                "text/html" | "application/vnd.ant.react" => vec![
+                    Line::from(format!("{} ({})", artifact.title, artifact.content_type)),
+                    Line::from(""),
+                    Line::from("This artifact is rendered in the browser - press Tab in the chat to open the latest one."),
+                    Line::from("Press s to read its source here."),
+                ],
+                "text/markdown" => self.render_text(&artifact.content, false, usize::MAX, 0),
+                content_type => {
+                    let fenced = format!("```{}\n{}\n```", source_extension(content_type), artifact.content);
+                    self.render_text(&fenced, false, usize::MAX, 0)
+                }
+            }
+        };
+        Overlay {
+            title: format!(
+                "Artifact {} of {}: {} - s for {}, c to copy, Esc to close",
+                index + 1,
+                self.artifacts.len(),
+                artifact.title,
+                if show_source { "the rendered view" } else { "the source" }
+            ),
+            lines,
+            scroll: 0,
+            copy_text: Some(artifact.content.clone()),
+            save_as_note: false,
+            artifact: Some(index),
+        }
+    }
+
+    fn attach(&mut self, path: &str) {
+        if path.is_empty() {
+            self.status_message = Some("Usage: /attach <path>".to_string());
+            return;
+        }
+
+        match load_attachment(Path::new(path)) {
+            Ok(block) => {
+                let name = Path::new(path)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_else(|| path.to_string());
+                self.pending_attachments.push((name, block));
+            }
+            Err(e) => {
+                self.status_message = Some(format!("Failed to attach {}: {}", path, e));
+            }
+        }
+    }
+
+    /// The conversation as sent to the API: the history plus, for a /retry-with, its
+    /// instructions added to the last user turn for this request only
+    fn request_messages(&self) -> Vec<Message> {
+        let mut messages = match self.config.max_history {
+            Some(max) => recent_messages(&self.messages, max).to_vec(),
+            None => self.messages.clone(),
+        };
+        let Some(steering) = &self.steering else {
+            return messages;
+        };
+        if let Some(message) = messages.iter_mut().rev().find(|message| message.is_user_turn()) {
+            let nudge = ContentBlock::Text {
+                text: format!("(For this reply: {})", steering),
+            };
+            message.content = match std::mem::replace(&mut message.content, MessageContent::Blocks(Vec::new())) {
+                MessageContent::Text(text) => MessageContent::Blocks(vec![ContentBlock::Text { text }, nudge]),
+                MessageContent::Blocks(mut blocks) => {
+                    blocks.push(nudge);
+                    MessageContent::Blocks(blocks)
+                }
+            };
+        }
+        messages
+    }
+
+    /// Sends the conversation and keeps answering tool calls until Claude finishes its turn or
+    /// the tool loop limit is reached
+    async fn send_message(&mut self, terminal: &mut Tui, model: Option<String>) -> Result<()> {
+        let model = model.unwrap_or_else(|| self.config.model().to_string());
+        let mut rounds = 0;
+
+        loop {
+            let mut builder = MessageRequest::builder()
+                .model(model.as_str())
+                .max_tokens(self.config.max_tokens())
+                .cache_conversation(self.config.cache_conversation)
+                .messages(self.request_messages());
+            if !self.config.no_tools {
+                builder = builder.tools(self.mcp_handler.tools());
+            }
+            if let Some(system) = &self.session.system {
+                builder = builder.system(system.clone());
+            }
+            if let Some(budget) = self.config.thinking_budget {
+                builder = builder.thinking_budget(budget);
+            }
+            if let Some(temperature) = self.config.temperature {
+                builder = builder.temperature(temperature);
+            }
+            if let Some(top_p) = self.config.top_p {
+                builder = builder.top_p(top_p);
+            }
+            if let Some(service_tier) = &self.config.service_tier {
+                builder = builder.service_tier(service_tier.clone());
+            }
+            let request = builder.build();
+
+            self.streaming = Some(String::new());
+            let result = self.stream_response(request, terminal).await;
+            self.streaming = None;
+            let response = match result? {
+                StreamOutcome::Complete(response) => response,
+                StreamOutcome::Cancelled { discard_prompt } => {
+                    self.cancel_turn(discard_prompt);
+                    return Ok(());
+                }
+            };
+
+            self.last_response = Some(response.clone());
+
+            let mut response_blocks = Vec::new();
+            let mut tool_calls = Vec::new();
+            let mut full_text = String::new();
+
+            for content in response.content {
+                match content {
+                    ResponseContent::Text { text } => {
+                        full_text.push_str(&text);
+                        response_blocks.push(ContentBlock::Text { text });
+                    }
+                    ResponseContent::ToolUse { id, name, input } => {
+                        tool_calls.push((id.clone(), name.clone(), input.clone()));
+                        response_blocks.push(ContentBlock::ToolUse { id, name, input });
+                    }
+                    thinking => response_blocks.extend(thinking.thinking_block()),
+                }
+            }
+
+            // Extract artifacts from the full text, acting on any whose type has a configured action
+            let new_artifacts = self.artifact_manager.extract_artifacts(&full_text);
+            let first_new = self.artifacts.len();
+            self.artifacts.extend(new_artifacts);
+            for index in first_new..self.artifacts.len() {
+                match self.artifact_manager.configured_action(&self.artifacts[index].content_type) {
+                    Some(ArtifactAction::Ignore) | None => {}
+                    Some(action) => self.handle_artifact(index, action),
+                }
+            }
+
+            // Add assistant response
+            let content = if let [ContentBlock::Text { text }] = response_blocks.as_slice() {
+                MessageContent::Text(text.clone())
+            } else {
+                MessageContent::Blocks(response_blocks)
+            };
+            self.session.usage.add(&response.usage);
+            // Everything worth reporting about the reply shares the status bar
+            let mut report = Vec::new();
+            if self.config.cache_conversation || response.usage.used_cache() {
+                report.push(format!("Input tokens: {}", response.usage.input_breakdown()));
+            }
+            // The API counts thinking as output without itemizing it, so this is an estimate
+            if let Some(budget) = self.config.thinking_budget {
+                let thinking_chars: usize = match &content {
+                    MessageContent::Blocks(blocks) => blocks
+                        .iter()
+                        .map(|block| match block {
+                            ContentBlock::Thinking { thinking, .. } => thinking.chars().count(),
+                            _ => 0,
+                        })
+                        .sum(),
+                    MessageContent::Text(_) => 0,
+                };
+                report.push(format!(
+                    "Thinking: ~{} of {} budget tokens, {} output tokens in total",
+                    approximate_tokens(thinking_chars),
+                    budget,
+                    response.usage.output_tokens
+                ));
+            }
+            if let (Some(_), Some(tier)) = (&self.config.service_tier, &response.usage.service_tier) {
+                report.push(format!("Served by the {} tier", tier));
+            }
+            if !report.is_empty() {
+                self.status_message = Some(report.join(" | "));
+            }
+            if response.stop_reason.as_deref() == Some("refusal") {
+                self.status_message = Some("Claude declined to respond - /undo to rephrase your message".to_string());
+            }
             self.messages.push(Message {
                 role: "assistant".to_string(),
-                content: MessageContent::Blocks(response_blocks),
+                content,
+                stop_reason: response.stop_reason,
+                model: Some(model.clone()),
             });
+
+            if tool_calls.is_empty() {
+                return Ok(());
+            }
+
+            let stop_tool = tool_calls
+                .iter()
+                .map(|(_, name, _)| name.clone())
+                .find(|name| self.config.stop_tools.contains(name));
+
+            // Every tool call gets a result, so the conversation stays valid even if we stop here
+            let mut results = Vec::new();
+            for (id, name, input) in tool_calls {
+                terminal.draw(|f| self.ui(f))?;
+                // Failures go back to Claude as an error result
+                let (tool_result, is_error) = if self.mcp_handler.needs_confirmation(&name)
+                    && !self.confirm_tool_call(&name, &input, terminal)?
+                {
+                    let reason = if name == "write_file" {
+                        "The user declined this file write"
+                    } else {
+                        "The user declined this tool call"
+                    };
+                    self.mcp_handler.record_declined(&name, &input, reason);
+                    (format!("Error: {}", reason).into(), true)
+                } else {
+                    match self.mcp_handler.handle_tool_call(&name, &input).await {
+                        Ok(result) => (ToolResultContent::from_output(result), false),
+                        Err(e) => {
+                            self.last_error = Some(format!("{} tool: {}", name, e));
+                            (format!("Error: {}", e).into(), true)
+                        }
+                    }
+                };
+                if let Some(failure) = self.mcp_handler.take_log_failure() {
+                    self.status_message = Some(failure);
+                }
+                results.push(ContentBlock::ToolResult {
+                    tool_use_id: id,
+                    content: tool_result,
+                    is_error,
+                });
+            }
+            self.messages.push(Message {
+                role: "user".to_string(),
+                content: MessageContent::Blocks(results),
+                stop_reason: None,
+                model: None,
+            });
+
+            if let Some(name) = stop_tool {
+                self.status_message = Some(format!("Claude called {} - send a message to let it continue", name));
+                return Ok(());
+            }
+
+            rounds += 1;
+            if rounds >= self.config.max_tool_iterations() {
+                self.status_message = Some(format!(
+                    "Tool loop limit reached ({} rounds) - send a message to let Claude continue",
+                    rounds
+                ));
+                return Ok(());
+            }
         }
+    }
 
-        Ok(())
+    /// Drops a cancelled response. Nothing from it was added to the conversation, so no tool call
+    /// is left without its result; optionally the prompt goes back to the input box as well
+    fn cancel_turn(&mut self, discard_prompt: bool) {
+        if !discard_prompt {
+            self.status_message = Some("Response cancelled".to_string());
+            return;
+        }
+        if self.messages.last().is_some_and(|message| message.is_user_turn()) {
+            let prompt = self.messages.pop().map(|message| message.text()).unwrap_or_default();
+            self.selected = self.selected.filter(|&selected| selected < self.messages.len());
+            // Anything drafted while the response streamed is kept after the restored prompt
+            self.input = if self.input.is_empty() {
+                prompt
+            } else {
+                format!("{} {}", prompt, self.input)
+            };
+        }
+        self.status_message = Some("Response cancelled and prompt returned to the input box".to_string());
+    }
+
+    /// Shows a proposed tool call and blocks until the user approves or rejects it: a diff for
+    /// write_file, the input for anything else
+    fn confirm_tool_call(&mut self, name: &str, input: &Value, terminal: &mut Tui) -> Result<bool> {
+        let (title, lines) = if name == "write_file" {
+            let path = input["path"].as_str().unwrap_or_default();
+            let content = input["content"].as_str().unwrap_or_default();
+            let current = std::fs::read_to_string(path).ok();
+            (format!("Write {}? y to approve, n to reject", path), render_diff(current.as_deref(), content))
+        } else {
+            let input = serde_json::to_string_pretty(input).unwrap_or_else(|_| input.to_string());
+            (format!("Run {}? y to approve, n to reject", name), plain_text(&input).lines)
+        };
+
+        self.overlay = Some(Overlay {
+            title,
+            lines,
+            scroll: 0,
+            copy_text: None,
+            save_as_note: false,
+            artifact: None,
+        });
+
+        let approved = loop {
+            terminal.draw(|f| self.ui(f))?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                let overlay = self.overlay.as_mut().expect("confirmation overlay is open");
+                match key.code {
+                    KeyCode::Char('y') | KeyCode::Char('Y') => break true,
+                    KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => break false,
+                    KeyCode::Up => overlay.scroll = overlay.scroll.saturating_sub(1),
+                    KeyCode::Down => overlay.scroll = overlay.scroll.saturating_add(1).min(self.max_overlay_scroll.get()),
+                    KeyCode::PageUp => overlay.scroll = overlay.scroll.saturating_sub(10),
+                    KeyCode::PageDown => overlay.scroll = overlay.scroll.saturating_add(10).min(self.max_overlay_scroll.get()),
+                    _ => {}
+                }
+            }
+        };
+
+        self.overlay = None;
+        Ok(approved)
+    }
+
+    async fn stream_response(&mut self, request: MessageRequest, terminal: &mut Tui) -> Result<StreamOutcome> {
+        terminal.draw(|f| self.ui(f))?;
+
+        let mut stream = self.client.stream_message(request).await?;
+        let mut last_draw = Instant::now();
+        let mut key_poll = tokio::time::interval(KEY_POLL_INTERVAL);
+        loop {
+            tokio::select! {
+                delta = stream.next_delta() => {
+                    let Some(delta) = delta? else { break };
+                    if let Some(partial) = self.streaming.as_mut() {
+                        partial.push_str(&delta);
+                    }
+                    if last_draw.elapsed() >= STREAM_REDRAW_INTERVAL {
+                        terminal.draw(|f| self.ui(f))?;
+                        last_draw = Instant::now();
+                    }
+                }
+                _ = key_poll.tick() => {
+                    if let Some(outcome) = self.poll_keys_while_streaming()? {
+                        return Ok(outcome);
+                    }
+                }
+            }
+        }
+
+        Ok(StreamOutcome::Complete(stream.into_response()))
+    }
+
+    /// Handles keys pressed mid-stream: Esc or Ctrl+C cancels (Esc with any modifier also drops
+    /// the prompt) and typing keeps editing the input box so the next message can be drafted
+    fn poll_keys_while_streaming(&mut self) -> Result<Option<StreamOutcome>> {
+        while event::poll(Duration::ZERO)? {
+            let Event::Key(key) = event::read()? else { continue };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Esc => {
+                    return Ok(Some(StreamOutcome::Cancelled {
+                        discard_prompt: !key.modifiers.is_empty(),
+                    }));
+                }
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(Some(StreamOutcome::Cancelled { discard_prompt: false }));
+                }
+                KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => self.input.push(c),
+                KeyCode::Backspace => {
+                    self.input.pop();
+                }
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// Rendered lines with code already fitted to `width`; prose is left for with_role_label to wrap
+    fn render_text(&self, text: &str, raw: bool, width: usize, pan: usize) -> Vec<Line<'static>> {
+        let rendered = if raw {
+            plain_text(text)
+        } else {
+            self.markdown_renderer.render_to_text(text, width, pan).unwrap_or_else(|_| plain_text(text))
+        };
+        rendered.lines
+    }
+
+    /// The message `/raw` applies to: the selected message, or else the latest assistant reply
+    fn raw_target(&self) -> Option<usize> {
+        self.selected
+            .or_else(|| self.messages.iter().rposition(|message| message.role == "assistant"))
+    }
+
+    /// A message's lines in the chat pane: role header, content and any stop notice. `pan`
+    /// scrolls its code blocks horizontally when they aren't wrapped
+    fn message_lines(&self, message: &Message, raw: bool, chat_width: usize, pan: usize) -> Vec<Line<'static>> {
+        let mut lines = Vec::new();
+        let role_style = if message.role == "user" {
+            Style::default().fg(self.palette.user).add_modifier(Modifier::BOLD)
+        } else {
+            Style::default().fg(self.palette.assistant).add_modifier(Modifier::BOLD)
+        };
+
+        // Replies from a model other than the configured one (e.g. via /retry) are labelled
+        let role = if message.role == "user" && !message.is_user_turn() { "tool" } else { self.config.role_label(&message.role) };
+        let model = message.model.as_deref().filter(|model| *model != self.config.model());
+        match &message.content {
+            MessageContent::Text(text) => {
+                lines.extend(self.render_text(text, raw, chat_width, pan));
+            }
+            MessageContent::Blocks(blocks) => {
+                for block in blocks {
+                    match block {
+                        ContentBlock::Text { text } => {
+                            lines.extend(self.render_text(text, raw, chat_width, pan));
+                        }
+                        ContentBlock::ToolUse { name, input, .. } => {
+                            lines.push(Line::from(Span::styled(
+                                format!("🔧 Tool: {} with input: {}", name, input),
+                                Style::default().fg(self.palette.tool_call),
+                            )));
+                        }
+                        ContentBlock::Image { source } | ContentBlock::Document { source } => {
+                            lines.push(Line::from(
+                                Span::styled(
+                                    format!("📎 Attachment ({})", source.media_type),
+                                    Style::default().fg(self.palette.attachment)
+                                )
+                            ));
+                        }
+                        ContentBlock::Thinking { thinking, .. } => {
+                            lines.push(Line::from(Span::styled(
+                                format!("💭 Thought for ~{} tokens", approximate_tokens(thinking.chars().count())),
+                                Style::default().fg(self.palette.muted).add_modifier(Modifier::ITALIC),
+                            )));
+                        }
+                        ContentBlock::RedactedThinking { .. } => {
+                            lines.push(Line::from(Span::styled(
+                                "💭 Thinking (redacted by the API)",
+                                Style::default().fg(self.palette.muted).add_modifier(Modifier::ITALIC),
+                            )));
+                        }
+                        ContentBlock::ToolResult { content: ToolResultContent::Text(text), .. } => {
+                            lines.extend(tool_output_lines(text, self.palette.tool_output));
+                        }
+                        ContentBlock::ToolResult { content: ToolResultContent::Blocks(blocks), .. } => {
+                            for block in blocks {
+                                match block {
+                                    ContentBlock::Text { text } => {
+                                        lines.extend(tool_output_lines(text, self.palette.tool_output));
+                                    }
+                                    ContentBlock::Image { source } => lines.push(Line::from(Span::styled(
+                                        format!(
+                                            "🖼 Image result ({}, {} KB)",
+                                            source.media_type,
+                                            source.data.len() * 3 / 4 / 1024
+                                        ),
+                                        Style::default().fg(self.palette.attachment),
+                                    ))),
+                                    _ => {}
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        if message.stop_reason.as_deref() == Some("refusal") {
+            lines.push(Line::from(Span::styled(
+                "⚠ Claude declined to respond to this request",
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            )));
+            lines.push(Line::from(Span::styled(
+                "  Use /undo to put your message back in the input box and rephrase it",
+                Style::default().fg(self.palette.muted).add_modifier(Modifier::ITALIC),
+            )));
+        } else if let Some(reason) = message.stop_reason.as_deref().filter(|reason| *reason != "end_turn") {
+            let explanation = match reason {
+                "max_tokens" => "response truncated at the max_tokens limit",
+                "stop_sequence" => "hit a stop sequence",
+                "tool_use" => "paused to use a tool",
+                _ => "stopped",
+            };
+            lines.push(Line::from(Span::styled(
+                format!("⏹ {} ({})", explanation, reason),
+                Style::default().fg(self.palette.muted).add_modifier(Modifier::ITALIC),
+            )));
+        }
+
+        self.with_role_label(role, model, role_style, lines, chat_width)
+    }
+
+    /// Puts the role label (and `model`, for replies from a model other than the configured one)
+    /// on its own line above a message, or in the compact layout a one-letter tag in front of its
+    /// first line, then wraps the message to `width`. Wrapping happens only here so the first
+    /// line is wrapped once, with the label's width already counted
+    fn with_role_label(
+        &self,
+        role: &str,
+        model: Option<&str>,
+        style: Style,
+        mut lines: Vec<Line<'static>>,
+        width: usize,
+    ) -> Vec<Line<'static>> {
+        if !self.compact {
+            let label = match model {
+                Some(model) => format!("{} ({}): ", role, model),
+                None => format!("{}: ", role),
+            };
+            lines.insert(0, Line::from(Span::styled(label, style)));
+        } else {
+            let tag: String = role.chars().next().into_iter().flat_map(char::to_uppercase).chain([' ']).collect();
+            match lines.first_mut() {
+                Some(first) => first.spans.insert(0, Span::styled(tag, style)),
+                None => lines.push(Line::from(Span::styled(tag, style))),
+            }
+        }
+        wrap_styled(lines, width)
+    }
+
+    /// The centered column the chat is laid out in: the whole screen unless `max_width` is set
+    /// and the terminal is wider than that many columns of text plus borders and margins
+    fn reading_column(&self, area: Rect, margin: u16) -> Rect {
+        let Some(max_width) = self.config.max_width else {
+            return area;
+        };
+        let width = area.width.min(max_width.saturating_add(2 + 2 * margin));
+        Rect {
+            x: area.x + (area.width - width) / 2,
+            width,
+            ..area
+        }
     }
 
     fn ui(&self, f: &mut Frame) {
-        let chunks = Layout::default()
-            .direction(Direction::Vertical)
-            .margin(1)
-            .constraints([
+        let palette = &self.palette;
+        // The transcript view gives the whole screen to the chat history
+        let constraints = if self.transcript {
+            vec![Constraint::Min(1)]
+        } else {
+            vec![
                 Constraint::Min(5),
                 Constraint::Length(3),
                 Constraint::Length(2),
-            ])
-            .split(f.size());
+            ]
+        };
+        let margin = if self.transcript || self.compact { 0 } else { 1 };
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .margin(margin)
+            .constraints(constraints)
+            .split(self.reading_column(f.size(), margin));
 
         // Chat history
+        let chat_width = chunks[0].width.saturating_sub(2) as usize;
         let mut chat_items = Vec::new();
-        for message in &self.messages {
-            let role_style = if message.role == "user" {
-                Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD)
+        let raw_index = if self.show_raw { self.raw_target() } else { None };
+        let mut selected_range = None;
+        let mut cache = self.render_cache.borrow_mut();
+        let mut fresh = HashMap::with_capacity(self.messages.len());
+        let mut message_rows = self.message_rows.borrow_mut();
+        message_rows.clear();
+        for (index, message) in self.messages.iter().enumerate() {
+            let raw = !self.render_markdown || raw_index == Some(index);
+            let message_start = chat_items.len();
+            message_rows.push(message_start);
+            let pan = if self.selected == Some(index) && self.config.code_overflow == Overflow::Scroll {
+                let max_pan = if raw { 0 } else { self.markdown_renderer.max_code_pan(&message.text(), chat_width) };
+                self.max_code_pan.set(max_pan);
+                self.code_pan.min(max_pan)
             } else {
-                Style::default().fg(Color::Green).add_modifier(Modifier::BOLD)
+                0
             };
+            // Only messages whose content (or the pane width) changed since the last frame get re-rendered
+            let key = render_key(message, raw, self.compact, chat_width, pan);
+            let lines = fresh
+                .entry(key)
+                .or_insert_with(|| cache.remove(&key).unwrap_or_else(|| self.message_lines(message, raw, chat_width, pan)));
+            chat_items.extend(lines.iter().cloned().map(ListItem::new));
 
-            chat_items.push(ListItem::new(Line::from(vec![
-                Span::styled(format!("{}: ", message.role), role_style)
-            ])));
+            if self.selected == Some(index) {
+                selected_range = Some(message_start..chat_items.len());
+            }
 
-            match &message.content {
-                MessageContent::Text(text) => {
-                    let rendered = self.markdown_renderer.render(text).unwrap_or_else(|_| text.clone());
-                    let lines: Vec<String> = rendered.lines().map(|s| s.to_string()).collect();
-                    for line in lines {
-                        chat_items.push(ListItem::new(Line::from(line)));
-                    }
-                }
-                MessageContent::Blocks(blocks) => {
-                    for block in blocks {
-                        match block {
-                            ContentBlock::Text { text } => {
-                                let rendered = self.markdown_renderer.render(text).unwrap_or_else(|_| text.clone());
-                                let lines: Vec<String> = rendered.lines().map(|s| s.to_string()).collect();
-                                for line in lines {
-                                    chat_items.push(ListItem::new(Line::from(line)));
-                                }
-                            }
-                            ContentBlock::ToolUse { name, input, .. } => {
-                                chat_items.push(ListItem::new(Line::from(
-                                    Span::styled(
-                                        format!("🔧 Tool: {} with input: {}", name, input),
-                                        Style::default().fg(Color::Yellow)
-                                    )
-                                )));
-                            }
-                            ContentBlock::ToolResult { content, .. } => {
-                                let lines: Vec<String> = content.lines().map(|s| s.to_string()).collect();
-                                for line in lines {
-                                    chat_items.push(ListItem::new(Line::from(
-                                        Span::styled(line, Style::default().fg(Color::Magenta))
-                                    )));
-                                }
-                            }
-                        }
-                    }
-                }
+            if !self.compact {
+                chat_items.push(ListItem::new(Line::from(""))); // Empty line separator
             }
+        }
 
-            chat_items.push(ListItem::new(Line::from(""))); // Empty line separator
+        *cache = fresh;
+
+        if let Some(partial) = &self.streaming {
+            let style = Style::default().fg(palette.assistant).add_modifier(Modifier::BOLD);
+            let rendered = if self.render_markdown {
+                self.markdown_renderer.render_streaming(partial, chat_width).unwrap_or_else(|_| plain_text(partial))
+            } else {
+                plain_text(partial)
+            };
+            let lines = self.with_role_label(self.config.role_label("assistant"), None, style, rendered.lines, chat_width);
+            chat_items.extend(lines.into_iter().map(ListItem::new));
         }
 
+        if let Some(range) = &selected_range {
+            for item in &mut chat_items[range.clone()] {
+                *item = item.clone().style(Style::default().bg(palette.selection));
+            }
+        }
+
+        let page_height = chunks[0].height.saturating_sub(2) as usize;
+        let max_scroll = chat_items.len().saturating_sub(page_height);
+        self.page_height.set(page_height);
+        self.max_scroll.set(max_scroll);
+
+        let mut list_state = ListState::default().with_offset(max_scroll - self.scroll_offset.min(max_scroll));
+        if let Some(range) = selected_range {
+            // Let the list pull the selected message into view
+            list_state.select(Some(range.start));
+        }
+
+        let title = if self.transcript {
+            "Transcript (↑/↓, PageUp/PageDown, Home/End to scroll; Ctrl+T or Esc to return)"
+        } else {
+            "Chat with Claude"
+        };
         let chat_list = List::new(chat_items)
-            .block(Block::default().borders(Borders::ALL).title("Chat with Claude"))
-            .style(Style::default().fg(Color::White));
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .style(Style::default().fg(palette.text));
 
-        f.render_widget(chat_list, chunks[0]);
+        f.render_stateful_widget(chat_list, chunks[0], &mut list_state);
+        if self.transcript {
+            return;
+        }
 
         // Input box
         let input_paragraph = Paragraph::new(self.input.as_str())
-            .style(Style::default().fg(Color::Yellow))
+            .style(Style::default().fg(palette.input))
             .block(Block::default().borders(Borders::ALL).title("Input (Press Enter to send, Ctrl+Q to quit, Tab to view latest artifact)"));
 
         f.render_widget(input_paragraph, chunks[1]);
 
         // Status
-        let status_text = if self.artifacts.is_empty() {
+        let status_text = if let Some(message) = &self.status_message {
+            message.clone()
+        } else if let Some(index) = self.selected {
+            format!(
+                "Message {} of {} selected - Alt+↑/↓ to move, Esc to clear",
+                index + 1,
+                self.messages.len()
+            )
+        } else if !self.pending_attachments.is_empty() {
+            let names: Vec<&str> = self.pending_attachments.iter().map(|(name, _)| name.as_str()).collect();
+            format!("📎 {} attachment(s) pending: {} - /detach to clear", names.len(), names.join(", "))
+        } else if self.artifacts.is_empty() {
             "No artifacts generated yet".to_string()
         } else {
             format!("{} artifact(s) available - Press Tab to view latest", self.artifacts.len())
         };
 
+        let mut status_title = format!("Status - {}", self.conversation_size());
+        if self.session.system.is_some() {
+            status_title.push_str(" - system prompt set (Ctrl+P to view)");
+        }
         let status = Paragraph::new(status_text)
-            .style(Style::default().fg(Color::Gray))
-            .block(Block::default().borders(Borders::ALL).title("Status"));
+            .style(Style::default().fg(palette.status))
+            .block(Block::default().borders(Borders::ALL).title(status_title));
 
         f.render_widget(status, chunks[2]);
+
+        if let Some(overlay) = &self.overlay {
+            let area = centered_rect(80, 70, f.size());
+            // Wrapped here rather than by the paragraph so the scroll limit counts wrapped rows
+            let lines = wrap_styled(overlay.lines.clone(), area.width.saturating_sub(2) as usize);
+            let max_scroll = lines.len().saturating_sub(area.height.saturating_sub(2) as usize);
+            let max_scroll = u16::try_from(max_scroll).unwrap_or(u16::MAX);
+            self.max_overlay_scroll.set(max_scroll);
+            let popup = Paragraph::new(lines)
+                .scroll((overlay.scroll.min(max_scroll), 0))
+                .block(Block::default().borders(Borders::ALL).title(overlay.title.as_str()));
+            f.render_widget(Clear, area);
+            f.render_widget(popup, area);
+        }
+    }
+}
+
+/// Tool output as shown in the chat
+fn tool_output_lines(text: &str, color: Color) -> Vec<Line<'static>> {
+    text.lines()
+        .map(|line| Line::from(Span::styled(line.to_string(), Style::default().fg(color))))
+        .collect()
+}
+
+/// Rough token count for a number of characters, at about four characters per token
+fn approximate_tokens(chars: usize) -> String {
+    let tokens = chars.div_ceil(APPROX_CHARS_PER_TOKEN);
+    if tokens < 1000 {
+        tokens.to_string()
+    } else {
+        format!("{:.1}k", tokens as f64 / 1000.0)
     }
 }
 
+/// Whether a message is the local note left by a failed request rather than a real reply
+fn is_error_reply(message: &Message) -> bool {
+    message.role == "assistant"
+        && message.model.is_none()
+        && matches!(&message.content, MessageContent::Text(text) if text.starts_with(ERROR_REPLY_PREFIX))
+}
+
+fn plain_text(text: &str) -> Text<'static> {
+    Text::from(text.lines().map(|line| Line::from(line.to_string())).collect::<Vec<_>>())
+}
+
+/// Identifies everything a message's rendered lines depend on
+fn render_key(message: &Message, raw: bool, compact: bool, width: usize, pan: usize) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    message.role.hash(&mut hasher);
+    message.stop_reason.hash(&mut hasher);
+    message.model.hash(&mut hasher);
+    raw.hash(&mut hasher);
+    compact.hash(&mut hasher);
+    width.hash(&mut hasher);
+    pan.hash(&mut hasher);
+    // Content is serialized straight into the hasher rather than into an intermediate string
+    let _ = serde_json::to_writer(HashWriter(&mut hasher), &message.content);
+    hasher.finish()
+}
 
+struct HashWriter<'a>(&'a mut DefaultHasher);
+
+impl io::Write for HashWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Copies text with the OSC 52 escape sequence, which most terminals (including over SSH)
+/// forward to the system clipboard
+fn copy_to_clipboard(text: &str) -> Result<()> {
+    let mut stdout = io::stdout();
+    write!(stdout, "\x1b]52;c;{}\x07", base64::engine::general_purpose::STANDARD.encode(text))?;
+    stdout.flush()?;
+    Ok(())
+}
+
+fn restore_terminal() -> Result<()> {
+    disable_raw_mode()?;
+    execute!(io::stdout(), LeaveAlternateScreen, DisableMouseCapture)?;
+    Ok(())
+}
+
+fn centered_rect(percent_x: u16, percent_y: u16, area: Rect) -> Rect {
+    let vertical = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Percentage((100 - percent_y) / 2),
+            Constraint::Percentage(percent_y),
+            Constraint::Percentage((100 - percent_y) / 2),
+        ])
+        .split(area);
+
+    Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Percentage((100 - percent_x) / 2),
+            Constraint::Percentage(percent_x),
+            Constraint::Percentage((100 - percent_x) / 2),
+        ])
+        .split(vertical[1])[1]
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::MediaSource;
+
+    fn message(role: &str, text: &str) -> Message {
+        Message {
+            role: role.to_string(),
+            content: MessageContent::Text(text.to_string()),
+            stop_reason: None,
+            model: None,
+        }
+    }
+
+    /// Where each cached message's lines live; an entry keeps its buffer while it's reused
+    fn cached_buffers(app: &ChatApp) -> HashMap<u64, *const Line<'static>> {
+        app.render_cache.borrow().iter().map(|(key, lines)| (*key, lines.as_ptr())).collect()
+    }
+
+    #[test]
+    fn undo_queues_the_turns_attachments_again() {
+        let mut app = ChatApp::new(ClaudeClient::new(String::new()), Config::default());
+        let image = ContentBlock::Image {
+            source: MediaSource {
+                source_type: "base64".to_string(),
+                media_type: "image/png".to_string(),
+                data: "iVBORw0KGgo=".to_string(),
+            },
+        };
+        app.messages = vec![Message {
+            role: "user".to_string(),
+            content: MessageContent::Blocks(vec![image, ContentBlock::Text { text: "what is this?".to_string() }]),
+            stop_reason: None,
+            model: None,
+        }];
+        app.messages.push(message("assistant", "a cat"));
+
+        app.undo();
+        assert!(app.messages.is_empty());
+        assert_eq!(app.input, "what is this?");
+        let queued: Vec<&str> = app.pending_attachments.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(queued, ["image/png"]);
+    }
+
+    #[test]
+    fn a_new_session_forgets_the_old_conversation() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let config = Config { output_dir: Some(output_dir.path().to_path_buf()), ..Config::default() };
+        let mut app = ChatApp::new(ClaudeClient::new(String::new()), config);
+        app.messages = vec![message("user", "hi"), message("assistant", "hello")];
+        app.last_response = Some(MessageResponse {
+            content: vec![ResponseContent::Text { text: "hello".to_string() }],
+            stop_reason: Some("end_turn".to_string()),
+            usage: Default::default(),
+        });
+        app.pending_attachments.push(("notes.txt".to_string(), ContentBlock::Text { text: "notes".to_string() }));
+        app.last_error = Some("overloaded".to_string());
+
+        app.new_session();
+        assert!(app.messages.is_empty());
+        assert!(app.last_response.is_none());
+        assert!(app.pending_attachments.is_empty());
+        assert!(app.last_error.is_none());
+    }
+
+    #[test]
+    fn compact_layout_tags_messages_with_a_letter() {
+        let mut app = ChatApp::new(ClaudeClient::new(String::new()), Config::default());
+        let first_line = |app: &ChatApp, message: &Message| -> String {
+            let lines = app.message_lines(message, true, 40, 0);
+            lines[0].spans.iter().map(|span| span.content.as_ref()).collect()
+        };
+        assert_eq!(first_line(&app, &message("user", "hi")), "user: ");
+
+        app.compact = true;
+        assert_eq!(first_line(&app, &message("user", "hi")), "U hi");
+        assert_eq!(first_line(&app, &message("assistant", "hello")), "A hello");
+    }
+
+    #[test]
+    fn editing_a_message_only_invalidates_its_cached_lines() {
+        let mut app = ChatApp::new(ClaudeClient::new(String::new()), Config::default());
+        app.messages = (0..5)
+            .map(|n| message(if n % 2 == 0 { "user" } else { "assistant" }, &format!("message {}", n)))
+            .collect();
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(80, 40)).unwrap();
+        let keys = |app: &ChatApp| -> Vec<u64> {
+            app.messages.iter().map(|message| render_key(message, false, false, 76, 0)).collect()
+        };
+
+        terminal.draw(|f| app.ui(f)).unwrap();
+        terminal.draw(|f| app.ui(f)).unwrap();
+        let before = cached_buffers(&app);
+        assert_eq!(before.len(), 5);
+
+        app.messages[2].content = MessageContent::Text("message 2, edited".to_string());
+        terminal.draw(|f| app.ui(f)).unwrap();
+        let after = cached_buffers(&app);
+        assert_eq!(after.len(), 5);
+
+        let rebuilt: Vec<usize> = keys(&app)
+            .iter()
+            .enumerate()
+            .filter(|(_, key)| before.get(key) != Some(&after[key]))
+            .map(|(index, _)| index)
+            .collect();
+        assert_eq!(rebuilt, vec![2]);
+    }
+}
@@ -0,0 +1,214 @@
+use ratatui::style::{Color, Style};
+use ratatui::text::{Line, Span};
+use serde::Deserialize;
+use unicode_width::UnicodeWidthChar;
+
+/// What happens to a line of code wider than the pane
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Overflow {
+    /// Continue on the next row
+    #[default]
+    Wrap,
+    /// Stay on one row, showing the columns from a horizontal scroll offset
+    Scroll,
+}
+
+/// Fits a line into `width` columns, either by wrapping it or by cropping it at `pan` columns
+/// from the left. Cropped lines get a `‹`/`›` marker on the side where text is hidden
+pub fn fit(line: Line<'static>, width: usize, overflow: Overflow, pan: usize) -> Vec<Line<'static>> {
+    match overflow {
+        Overflow::Wrap => hard_wrap(line, width),
+        Overflow::Scroll => vec![crop(line, width, pan)],
+    }
+}
+
+/// Wraps styled lines to `width` terminal columns, preferring to break at spaces and keeping
+/// each span's style. Continuation rows get a hanging indent past the line's own leading
+/// whitespace so nested output like JSON or list items keeps its shape.
+pub fn wrap_styled(lines: impl IntoIterator<Item = Line<'static>>, width: usize) -> Vec<Line<'static>> {
+    lines.into_iter().flat_map(|line| wrap_line(line, width)).collect()
+}
+
+fn wrap_line(line: Line<'static>, width: usize) -> Vec<Line<'static>> {
+    if width == 0 || line.width() <= width {
+        return vec![line];
+    }
+
+    let cells: Vec<(char, Style)> = line
+        .spans
+        .iter()
+        .flat_map(|span| span.content.chars().map(move |c| (c, span.style)))
+        .collect();
+    let leading: usize = cells
+        .iter()
+        .take_while(|(c, _)| c.is_whitespace())
+        .map(|(c, _)| char_width(*c))
+        .sum();
+    let indent = (leading + 2).min(width / 2);
+
+    let mut rows = Vec::new();
+    let mut start = 0;
+    let mut prefix = 0;
+    while start < cells.len() {
+        // Always take at least one character so a glyph wider than the row can't stall us
+        let mut end = start;
+        let mut used = prefix;
+        while end < cells.len() {
+            let char_width = char_width(cells[end].0);
+            if end > start && used + char_width > width {
+                break;
+            }
+            used += char_width;
+            end += 1;
+        }
+        if end < cells.len() {
+            if let Some(space) = cells[start..end].iter().rposition(|(c, _)| *c == ' ').filter(|&i| i > 0) {
+                end = start + space + 1;
+            }
+        }
+
+        let mut row = &cells[start..end];
+        while let Some(((' ', _), rest)) = row.split_last() {
+            row = rest;
+        }
+        let mut wrapped = styled_row(prefix, row);
+        wrapped.alignment = line.alignment;
+        rows.push(wrapped);
+
+        start = end;
+        prefix = indent;
+    }
+
+    rows
+}
+
+/// Splits a line into rows of exactly `width` columns, without looking for spaces or indenting
+/// continuations, for code where the characters themselves must stay put
+pub fn hard_wrap(line: Line<'static>, width: usize) -> Vec<Line<'static>> {
+    if width == 0 || line.width() <= width {
+        return vec![line];
+    }
+
+    let cells: Vec<(char, Style)> = line
+        .spans
+        .iter()
+        .flat_map(|span| span.content.chars().map(move |c| (c, span.style)))
+        .collect();
+    let mut rows = Vec::new();
+    let mut start = 0;
+    while start < cells.len() {
+        let mut end = start;
+        let mut used = 0;
+        while end < cells.len() {
+            let char_width = char_width(cells[end].0);
+            if end > start && used + char_width > width {
+                break;
+            }
+            used += char_width;
+            end += 1;
+        }
+        rows.push(styled_row(0, &cells[start..end]));
+        start = end;
+    }
+    rows
+}
+
+fn crop(line: Line<'static>, width: usize, pan: usize) -> Line<'static> {
+    let total = line.width();
+    if width == 0 || (pan == 0 && total <= width) {
+        return line;
+    }
+
+    let mut cells = Vec::new();
+    let mut column = 0;
+    for (c, style) in line.spans.iter().flat_map(|span| span.content.chars().map(move |c| (c, span.style))) {
+        let char_width = char_width(c);
+        if column >= pan && column + char_width <= pan + width {
+            cells.push((c, style));
+        }
+        column += char_width;
+    }
+
+    let marker_style = Style::default().fg(Color::DarkGray);
+    if pan + width < total {
+        cells.pop();
+        cells.push(('›', marker_style));
+    }
+    if pan > 0 && !cells.is_empty() {
+        cells[0] = ('‹', marker_style);
+    }
+    styled_row(0, &cells)
+}
+
+/// Rebuilds a line from characters, merging runs that share a style back into single spans
+fn styled_row(indent: usize, cells: &[(char, Style)]) -> Line<'static> {
+    let mut spans = Vec::new();
+    if indent > 0 {
+        spans.push(Span::raw(" ".repeat(indent)));
+    }
+
+    let mut text = String::new();
+    let mut style = None;
+    for &(c, cell_style) in cells {
+        if style.is_some_and(|style| style != cell_style) {
+            spans.push(Span::styled(std::mem::take(&mut text), style.unwrap_or_default()));
+        }
+        style = Some(cell_style);
+        text.push(c);
+    }
+    if let Some(style) = style {
+        spans.push(Span::styled(text, style));
+    }
+
+    Line::from(spans)
+}
+
+fn char_width(c: char) -> usize {
+    c.width().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::style::Modifier;
+
+    fn text(line: &Line) -> String {
+        line.spans.iter().map(|span| span.content.as_ref()).collect()
+    }
+
+    #[test]
+    fn continuations_hang_past_leading_whitespace() {
+        let rows = wrap_styled([Line::from("  - one two three four")], 12);
+        let rows: Vec<String> = rows.iter().map(text).collect();
+        assert_eq!(rows, ["  - one two", "    three", "    four"]);
+    }
+
+    #[test]
+    fn wide_glyphs_move_to_the_next_row_whole() {
+        let rows = wrap_styled([Line::from("abc漢字")], 4);
+        let rows: Vec<String> = rows.iter().map(text).collect();
+        assert_eq!(rows, ["abc", "  漢", "  字"]);
+        assert!(hard_wrap(Line::from("abc漢字"), 4).iter().all(|row| row.width() <= 4));
+    }
+
+    #[test]
+    fn styles_survive_a_break() {
+        let bold = Style::default().add_modifier(Modifier::BOLD);
+        let line = Line::from(vec![Span::raw("plain "), Span::styled("bold words here", bold)]);
+        let rows = wrap_styled([line], 12);
+        assert_eq!(text(&rows[0]), "plain bold");
+        assert_eq!(rows[0].spans[1].style, bold);
+        let continuation = rows[1].spans.last().unwrap();
+        assert_eq!(continuation.content, "words here");
+        assert_eq!(continuation.style, bold);
+    }
+
+    #[test]
+    fn cropped_lines_mark_hidden_text() {
+        let line = Line::from("0123456789");
+        assert_eq!(text(&crop(line.clone(), 5, 0)), "0123›");
+        assert_eq!(text(&crop(line.clone(), 5, 3)), "‹456›");
+        assert_eq!(text(&crop(line, 5, 5)), "‹6789");
+    }
+}